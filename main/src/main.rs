@@ -1,12 +1,41 @@
 
-/// this here is to test my knowledge on adaptive bitrate streaming
+//! this here is to test my knowledge on adaptive bitrate streaming
 
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+// Compiled out entirely unless the `logging` feature is enabled
+macro_rules! abr_debug {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "logging")]
+        log::debug!($($arg)+);
+    };
+}
+
+macro_rules! abr_trace {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "logging")]
+        log::trace!($($arg)+);
+    };
+}
 
-use std::collections::VecDeque;
-use std::time::{Duration, Instant};
+const SEEKING_BUFFER_FACTOR: f64 = 1.5;
+const RECENT_SEGMENT_COUNT: usize = 10;
+const DEFAULT_SEGMENT_DURATION: Duration = Duration::from_secs(4);
+const SEGMENT_HISTORY_CAPACITY: usize = 50;
+const CONSUMPTION_EVENT_CAPACITY: usize = 256;
+const MAX_MPC_HORIZON: usize = 5;
+const LADDER_CHECK_FPS: f32 = 30.0;
+const STABLE_BANDWIDTH_CV: f64 = 0.1;
+const VOLATILE_BANDWIDTH_CV: f64 = 0.5;
+const STEADY_STATE_SAMPLES: u32 = 5;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct QualityLevel {
     pub bitrate: u32,      // bits per second
     pub width: u32,
@@ -14,12 +43,208 @@ pub struct QualityLevel {
     pub codec: String,
 }
 
+impl QualityLevel {
+    pub fn bits_per_pixel(&self, fps: f32) -> f64 {
+        let pixels_per_second: f64 = self.width as f64 * self.height as f64 * fps as f64;
+        if pixels_per_second.is_nan() || pixels_per_second <= 0.0 {
+            return 0.0;
+        }
+        finite_or(self.bitrate as f64 / pixels_per_second, 0.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LadderWarning {
+    // A rendition gets fewer bits per pixel than the one below it
+    BitsPerPixelInversion {
+        index: usize,
+        bits_per_pixel: f64,
+        lower_bits_per_pixel: f64,
+    },
+}
+
+// What to do with a segment that does not fit in the buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferFullPolicy {
+    Clamp, // keep the buffer at max_level, dropping the excess
+    RejectDownload, // leave the buffer unchanged
+    IncreaseMax, // grow max_level to fit the segment
+}
+
+// When a rendition counts as fitting the bandwidth budget
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BoundaryPolicy {
+    StrictFit, // its bitrate must not exceed the budget
+    NearestWithinTolerance(f64), // it may exceed the budget by this fraction
+}
+
+// How a sample's weight in the weighted average falls off with its age,
+// measured in bandwidth windows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecayKernel {
+    Exponential, // exp(-age / window)
+    Linear, // 1 - age / window, reaching zero at the window edge
+    None, // every sample weighs the same
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyMode {
+    Normal,
+    // Keep within `target_latency` of the live edge rather than building depth
+    LowLatency { target_latency: Duration },
+}
+
+// Tie-break among renditions that all fit the bandwidth budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionPreference {
+    Bitrate,
+    Resolution,
+}
+
+// Buffer bands delimited by the panic, target and seek thresholds, with the
+// same boundary inclusivity as the buffer factor curve
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferZone {
+    Panic,
+    BelowTarget,
+    Normal,
+    Seeking,
+}
+
+// Whether the session's first throughput sample, often measured during TCP
+// slow start, takes part in estimation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirstSamplePolicy {
+    Include,
+    Exclude { decisions: u32 }, // left out of the next `decisions` decisions
+}
+
+// How a download that completed in under a millisecond (typically a cache
+// hit) feeds the bandwidth estimate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZeroDurationPolicy {
+    Clamp, // measure it as if it took one millisecond
+    Ignore, // count the segment but record no bandwidth sample
+    MaxBandwidth, // record an unbounded sample
+}
+
+// Caller-defined resolution bucket: a rendition belongs to the tier with the
+// highest `min_height` it reaches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ResolutionTier {
+    pub name: &'static str,
+    pub min_height: u32,
+}
+
+impl ResolutionTier {
+    pub const SD: ResolutionTier = ResolutionTier { name: "SD", min_height: 0 };
+    pub const HD: ResolutionTier = ResolutionTier { name: "HD", min_height: 720 };
+    pub const UHD: ResolutionTier = ResolutionTier { name: "UHD", min_height: 2160 };
+}
+
+// Adjacent renditions too far apart for adaptation to step between smoothly
+#[derive(Debug, Clone, PartialEq)]
+pub struct LadderGap {
+    pub lower_index: usize,
+    pub upper_index: usize,
+    pub bitrate_ratio: f64,
+}
+
+// Source of wall-clock time, replaceable so time-based behavior can be tested
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// Clock a replay moves by hand to each recorded timestamp
+struct ReplayClock {
+    now: Rc<Cell<Instant>>,
+}
+
+impl Clock for ReplayClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+}
+
+// Every stochastic decision draws from this, so seeded runs are reproducible
+pub trait RngSource {
+    // Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64;
+}
+
+// SplitMix64: small, seedable and good enough for exploration decisions
+#[derive(Debug, Clone)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+}
+
+impl RngSource for SeededRng {
+    fn next_f64(&mut self) -> f64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z: u64 = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentType {
+    Main,
+    Ad,
+}
+
 #[derive(Debug)]
 pub struct SegmentInfo {
     pub quality_level: usize,
+    pub bitrate: u32, // kept so history survives ladder updates
     pub size_bytes: u32,
     pub duration: Duration,
     pub download_time: Duration,
+    pub content_type: ContentType,
+    pub recorded_at: Instant,
+    pub complexity: Option<f32>, // scene complexity hint from the manifest, if any
+}
+
+// One finished download as reported by the player, for `step`
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentDownload {
+    pub segment_size: u32,
+    pub download_duration: Duration,
+    pub segment_duration: Duration,
+}
+
+// Running totals per content type, kept outside segment_history so they are not capped
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ContentStats {
+    pub segment_count: usize,
+    pub total_bytes: u64,
+    pub total_duration: Duration,
+    pub total_download_time: Duration,
+}
+
+impl ContentStats {
+    pub fn average_segment_duration(&self) -> Option<Duration> {
+        if self.segment_count == 0 {
+            return None;
+        }
+        Some(self.total_duration / self.segment_count as u32)
+    }
 }
 
 #[derive(Debug)]
@@ -30,346 +255,5849 @@ pub struct BufferState {
     pub min_level: Duration,
 }
 
+// Unit the player speaks in for buffer amounts; bytes are converted to media
+// time at the current rendition's bitrate. Internally the buffer stays in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferUnit {
+    Duration, // seconds
+    Bytes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkClass {
+    Cellular,
+    Wifi,
+    Ethernet,
+    Unknown,
+}
+
+impl NetworkClass {
+    // Typical throughput assumed before any samples arrive, in bytes per second
+    pub fn initial_bandwidth_estimate(&self) -> Option<u32> {
+        match self {
+            NetworkClass::Cellular => Some(187_500),  // 1.5 Mbps
+            NetworkClass::Wifi => Some(625_000),      // 5 Mbps
+            NetworkClass::Ethernet => Some(1_250_000), // 10 Mbps
+            NetworkClass::Unknown => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityDecision {
+    pub quality: usize,
+    pub previous_quality: usize,
+    pub target_quality: usize, // highest quality the effective bandwidth allows
+    pub estimated_bandwidth: u32,
+    pub effective_bandwidth: u32,
+    pub buffer_level: Duration,
+    pub clamped_from: Option<usize>, // set when smoothing kept the decision away from the target
+    pub upswitch_suppressed_by: Option<SuppressionReason>,
+}
+
+// Gate that held back an up-switch the selection logic had already proposed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuppressionReason {
+    Margin, // the level's up-switch headroom was not met
+    BufferSafety, // the segment could not be fetched before the buffer ran dry
+    Deadline, // the segment would miss its live availability deadline
+    RateLimit, // the previous switch was too recent
+    Transient, // the improvement had not yet held for min_upswitch_samples samples
+    StartupCap, // confidence and buffer were not yet established
+    LiveEdge, // downloads would fall behind real time in low-latency mode
+    ResolutionCap, // the bandwidth would carry a rendition larger than the viewport
+}
+
+// State the decision was made from
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecisionInput {
+    pub current_quality: usize,
+    pub estimated_bandwidth: u32,
+    pub bandwidth_samples: usize,
+    pub buffer_level: Duration,
+    pub buffer_factor: f64,
+    pub utilization: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoggedDecision {
+    pub input: DecisionInput,
+    pub decision: QualityDecision,
+}
+
+// Linear QoE: bitrate utility minus rebuffer and switching penalties
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QoeWeights {
+    pub bitrate: f64,  // per second of content, scaled by log utility
+    pub rebuffer: f64, // per second of stall
+    pub switch: f64,   // per unit of utility change between segments
+}
+
+impl Default for QoeWeights {
+    fn default() -> Self {
+        Self {
+            bitrate: 1.0,
+            rebuffer: 4.3,
+            switch: 1.0,
+        }
+    }
+}
+
+// What a QoE model gets to score: either the recorded session or a
+// candidate plan projected by the MPC planner
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionStats {
+    pub bitrates: Vec<u32>, // bits per second of each segment, in order
+    pub utilities: Vec<f64>, // log utility of each segment
+    pub segment_durations: Vec<Duration>,
+    pub rebuffer_time: Duration,
+    pub switches: u32,
+    pub switch_magnitude: f64, // sum of utility changes between segments
+}
+
+pub trait QoeModel {
+    fn score(&self, session: &SessionStats) -> f64;
+}
+
+// The standard linear model, same terms as `compute_qoe`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinearQoe {
+    pub weights: QoeWeights,
+}
+
+impl QoeModel for LinearQoe {
+    fn score(&self, session: &SessionStats) -> f64 {
+        let utility: f64 = session.utilities
+            .iter()
+            .zip(&session.segment_durations)
+            .map(|(utility, duration)| utility * duration.as_secs_f64())
+            .sum();
+        
+        self.weights.bitrate * utility
+            - self.weights.rebuffer * session.rebuffer_time.as_secs_f64()
+            - self.weights.switch * session.switch_magnitude
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecisionMode {
+    Default, // throughput estimate scaled by buffer factor, then step smoothing
+    Mpc { horizon: usize }, // plan `horizon` segments ahead, maximizing the active QoE model
+    ModeSmoothed { window: usize }, // most frequent of the last `window` raw targets
+    EpsilonGreedy { epsilon: f64 }, // default decision, but probes one level higher with probability epsilon
+    Hybrid { throughput_weight: f64 }, // blend of throughput-only and buffer-only targets, 1.0 is pure throughput
+}
+
+// Point-in-time snapshot of the streamer's counters, cheap to clone and log
+#[derive(Debug, Clone, PartialEq)]
+pub struct AbrMetrics {
+    pub total_segments: usize,
+    pub total_bytes: u64,
+    pub rebuffer_count: u32,
+    pub rebuffer_time: Duration,
+    pub up_switches: u32,
+    pub down_switches: u32,
+    pub quality_distribution: Vec<Duration>, // downloaded content duration per quality index
+    pub estimated_bandwidth: u32, // as of the last decision
+    pub buffer_level: Duration,
+    pub session_id: String,
+    pub session_elapsed: Duration,
+}
+
+// Each conservative layer applied between the raw throughput samples and the
+// bandwidth budget used for selection. Disabling a layer makes it neutral (1.0).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UtilizationLayers {
+    pub min_of_estimators: bool, // otherwise the weighted average alone is used
+    pub buffer_factor: bool,
+    pub safety_factor: bool,
+}
+
+impl Default for UtilizationLayers {
+    fn default() -> Self {
+        Self {
+            min_of_estimators: true,
+            buffer_factor: true,
+            safety_factor: true,
+        }
+    }
+}
+
+// The main tunables as one value, so alternative configurations can be
+// stored, compared and replayed. Defaults match a fresh streamer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamerConfig {
+    pub safety_factor: f32,
+    pub startup_safety_factor: f64,
+    pub panic_factor: f64,
+    pub panic_threshold: Duration,
+    pub min_bandwidth_samples: usize,
+    pub upswitch_margin: f64,
+    pub selection_hysteresis: f64,
+    pub decision_mode: DecisionMode,
+    pub utilization_layers: UtilizationLayers,
+}
+
+impl Default for StreamerConfig {
+    fn default() -> Self {
+        Self {
+            safety_factor: 0.8,
+            startup_safety_factor: 0.8,
+            panic_factor: 0.3,
+            panic_threshold: Duration::from_secs(3),
+            min_bandwidth_samples: 3,
+            upswitch_margin: 1.0,
+            selection_hysteresis: 0.0,
+            decision_mode: DecisionMode::Default,
+            utilization_layers: UtilizationLayers::default(),
+        }
+    }
+}
+
+impl StreamerConfig {
+    // Same bounds the individual setters enforce
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(self.safety_factor > 0.0 && self.safety_factor <= 1.0) {
+            return Err(ConfigError::OutOfRange { parameter: "safety_factor", value: self.safety_factor as f64 });
+        }
+        if !(self.startup_safety_factor > 0.0 && self.startup_safety_factor <= 1.0) {
+            return Err(ConfigError::OutOfRange { parameter: "startup_safety_factor", value: self.startup_safety_factor });
+        }
+        if !(self.panic_factor > 0.0 && self.panic_factor < 1.0) {
+            return Err(ConfigError::OutOfRange { parameter: "panic_factor", value: self.panic_factor });
+        }
+        if !(self.upswitch_margin >= 1.0 && self.upswitch_margin.is_finite()) {
+            return Err(ConfigError::OutOfRange { parameter: "upswitch_margin", value: self.upswitch_margin });
+        }
+        if !(0.0..1.0).contains(&self.selection_hysteresis) {
+            return Err(ConfigError::OutOfRange { parameter: "selection_hysteresis", value: self.selection_hysteresis });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    QualityIndexOutOfRange(usize),
+    EmptyLadder,
+    OutOfRange { parameter: &'static str, value: f64 },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::QualityIndexOutOfRange(index) => {
+                write!(f, "quality index {} is out of range", index)
+            }
+            ConfigError::EmptyLadder => write!(f, "quality ladder is empty"),
+            ConfigError::OutOfRange { parameter, value } => {
+                write!(f, "{} value {} is out of range", parameter, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Float results leaving the public API go through this so a degenerate
+// configuration can't leak NaN or infinity into selection
+fn finite_or(value: f64, fallback: f64) -> f64 {
+    if value.is_finite() { value } else { fallback }
+}
+
+// Bits per second to bytes per second, rounded to nearest
+fn bitrate_to_byte_rate(bits_per_second: u32) -> u32 {
+    ((bits_per_second as u64 + 4) / 8) as u32
+}
+
+// Bytes per second to bits per second, saturating at u32::MAX
+fn byte_rate_to_bitrate(bytes_per_second: u32) -> u32 {
+    (bytes_per_second as u64 * 8).min(u32::MAX as u64) as u32
+}
+
+// Unique within the process and unlikely to repeat across processes
+fn generate_session_id() -> String {
+    static SESSION_COUNTER: AtomicU64 = AtomicU64::new(0);
+    
+    let nanos: u64 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or(0);
+    let sequence: u64 = SESSION_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:016x}-{:04x}", nanos, sequence)
+}
+
+// Log utility of each rendition relative to the lowest bitrate in the ladder
+fn compute_utilities(quality_levels: &[QualityLevel]) -> Vec<f64> {
+    let lowest_bitrate: f64 = quality_levels
+        .iter()
+        .map(|quality| quality.bitrate)
+        .min()
+        .unwrap_or(1)
+        .max(1) as f64;
+    
+    quality_levels
+        .iter()
+        .map(|quality| (quality.bitrate.max(1) as f64 / lowest_bitrate).ln())
+        .collect()
+}
+
 pub struct AdaptiveBitrateStreamer {
+    clock: Box<dyn Clock>,
+    session_id: String,
+    session_start: Instant,
+    rng: Box<dyn RngSource>,
+    adaptation_started: bool, // latched once the startup gate has opened
+    startup_established: bool, // latched once the estimate is confident and the buffer healthy
+    adaptation_paused: bool,
+    pending_probe: bool, // the next recorded segment is an exploration probe
+    exploration_probes: u32,
+    exploration_successes: u32, // probes that downloaded faster than real time
     quality_levels: Vec<QualityLevel>,
+    utilities: Vec<f64>,
     current_quality: usize,
     bandwidth_history: VecDeque<(Instant, u32)>, // (timestamp, bytes_per_second)
+    lifetime_bandwidth_sum: u64, // over every sample ever recorded, not just the window
+    lifetime_bandwidth_samples: u64,
+    transferred_bytes: u64, // on-wire bytes, including retransmissions
+    peak_bandwidth: u32, // highest measured sample since construction or reset
+    first_sample: Option<(Instant, u32)>,
+    steady_samples: u32, // consecutive samples recorded with a stable coefficient of variation
+    decisions_since_first_sample: u32,
     buffer_state: BufferState,
+    buffer_full_policy: BufferFullPolicy,
+    buffer_unit: BufferUnit,
+    zero_duration_policy: ZeroDurationPolicy,
+    first_sample_policy: FirstSamplePolicy,
     segment_history: VecDeque<SegmentInfo>,
+    content_stats: HashMap<ContentType, ContentStats>,
+    time_at_quality: Vec<Duration>,
+    
+    rebuffer_count: u32,
+    rebuffer_time: Duration,
+    rebuffer_events: VecDeque<(Instant, Duration)>, // (when, stall duration)
+    consumption_events: VecDeque<(Instant, Duration)>, // (when, buffer drained by playback)
+    last_buffer_zone: BufferZone,
+    session_utility: f64, // utility x content seconds
+    session_switch_utility: f64, // sum of utility changes between segments
+    in_rebuffer: bool,
+    current_stall: Duration, // time spent empty in the ongoing stall
+    stall_counted: bool, // the ongoing stall outlasted the grace period
+    segments_since_rebuffer: Option<u32>, // None until the first rebuffer
+    post_rebuffer_discount: f64, // estimate multiplier right after a rebuffer
+    post_rebuffer_decay_segments: u32,
+    up_switches: u32,
+    down_switches: u32,
+    quality_entered_at: Option<Instant>, // when current_quality was first committed
+    last_switch_at: Option<Instant>, // None until the first switch this session
+    stable_quality_duration: Duration,
+    last_stable_quality: Option<usize>, // sustained quality left during a dip, until regained
+    last_estimated_bandwidth: u32,
+    recent_estimates: VecDeque<u32>, // blended estimate behind each recent decision, oldest first
+    decision_mode: DecisionMode,
+    raw_target_history: VecDeque<usize>,
+    qoe_model: Box<dyn QoeModel>,
+    decision_log: Vec<LoggedDecision>, // oldest first
+    decision_log_capacity: usize, // 0 disables the log
     
     bandwidth_window: Duration,
+    time_bucket: Option<Duration>,
+    size_weighted_estimate: bool,
+    decay_kernel: DecayKernel,
+    steady_state_estimator: bool,
     safety_factor: f32,
+    startup_safety_factor: f64, // extra safety while the estimate is not yet confident
     buffer_panic_threshold: Duration,
+    panic_factor: f64,
     buffer_seek_threshold: Duration,
+    seek_threshold_ratio: Option<f64>, // overrides buffer_seek_threshold as a fraction of max_level
+    segments_in_seeking_mode: u32, // consecutive seeking segments without confirmed headroom
+    seek_decay_segments: u32,
     min_bandwidth_samples: usize,
+    min_bandwidth_floor: u32, // bytes per second
+    bandwidth_ceiling: Option<u32>, // bytes per second; known link cap
+    network_class: NetworkClass,
+    initial_bandwidth_estimate: Option<u32>, // bytes per second, used while there are no samples
+    next_segment_deadline: Option<Instant>,
+    next_segment_size_hint: Option<(usize, u32)>, // (quality it was given for, bytes)
+    deadline_margin: Duration,
+    deadline_misses: u32,
+    empirical_segment_sizes: bool,
+    upswitch_buffer_safety: bool,
+    adapt_only_after_min_buffer: bool,
+    min_switch_interval: Option<Duration>, // at most one switch per interval
+    max_startup_quality: Option<usize>, // ceiling until startup is established
+    min_upswitch_samples: usize, // consecutive samples that must support an up-switch
+    panic_bandwidth_floor: bool,
+    latency_mode: LatencyMode,
+    target_warmup: Duration,
+    boundary_policy: BoundaryPolicy,
+    selection_preference: SelectionPreference,
+    max_upswitch_steps_when_full: usize, // up-switch step limit above the seek threshold
+    selection_hysteresis: f64, // extra fraction of bitrate needed to select a level above current
+    upswitch_margin: f64, // headroom over a level's bitrate required to step up into it
+    upswitch_margin_per_quality: Vec<Option<f64>>, // None falls back to upswitch_margin
+    freeze_timeout: Duration,
+    rebuffer_grace: Duration, // stalls this short are not counted as rebuffers
+    last_playhead: Option<(Duration, Instant)>, // (playhead, when it was last seen advancing)
+    frozen: bool,
+    min_buffer_per_quality: Vec<Option<Duration>>, // None falls back to buffer_state.min_level
+    utilization_layers: UtilizationLayers,
+    buffer_zone_listener: Option<Box<dyn FnMut(BufferZone, BufferZone)>>, // (old, new)
+    ladder_gap_threshold: f64,
+    supported_codecs: Option<HashSet<String>>, // None accepts every codec
+    viewport: Option<(u32, u32)>, // (width, height) the video is rendered into
 }
 
 impl AdaptiveBitrateStreamer {
     pub fn new(quality_levels: Vec<QualityLevel>) -> Self {
         let initial_quality: usize = quality_levels.len() / 2; // Start with middle quality
+        let min_buffer_per_quality: Vec<Option<Duration>> = vec![None; quality_levels.len()];
+        let upswitch_margin_per_quality: Vec<Option<f64>> = vec![None; quality_levels.len()];
+        let time_at_quality: Vec<Duration> = vec![Duration::from_secs(0); quality_levels.len()];
         
         Self {
+            clock: Box::new(SystemClock),
+            session_id: generate_session_id(),
+            session_start: Instant::now(),
+            rng: Box::new(SeededRng::new(0)),
+            adaptation_started: false,
+            startup_established: false,
+            adaptation_paused: false,
+            pending_probe: false,
+            exploration_probes: 0,
+            exploration_successes: 0,
+            utilities: compute_utilities(&quality_levels),
             quality_levels,
             current_quality: initial_quality,
             bandwidth_history: VecDeque::new(),
+            lifetime_bandwidth_sum: 0,
+            lifetime_bandwidth_samples: 0,
+            transferred_bytes: 0,
+            peak_bandwidth: 0,
+            first_sample: None,
+            steady_samples: 0,
+            decisions_since_first_sample: 0,
             buffer_state: BufferState {
                 current_level: Duration::from_secs(0),
                 target_level: Duration::from_secs(30),
                 max_level: Duration::from_secs(60),
                 min_level: Duration::from_secs(5),
             },
+            buffer_full_policy: BufferFullPolicy::Clamp,
+            buffer_unit: BufferUnit::Duration,
+            zero_duration_policy: ZeroDurationPolicy::MaxBandwidth,
+            first_sample_policy: FirstSamplePolicy::Include,
             segment_history: VecDeque::new(),
+            content_stats: HashMap::new(),
+            time_at_quality,
+            rebuffer_count: 0,
+            rebuffer_time: Duration::from_secs(0),
+            rebuffer_events: VecDeque::new(),
+            consumption_events: VecDeque::new(),
+            last_buffer_zone: BufferZone::Panic, // the buffer starts empty
+            session_utility: 0.0,
+            session_switch_utility: 0.0,
+            in_rebuffer: false,
+            current_stall: Duration::from_secs(0),
+            stall_counted: false,
+            segments_since_rebuffer: None,
+            post_rebuffer_discount: 1.0,
+            post_rebuffer_decay_segments: 0,
+            up_switches: 0,
+            down_switches: 0,
+            quality_entered_at: None,
+            last_switch_at: None,
+            stable_quality_duration: Duration::from_secs(10),
+            last_stable_quality: None,
+            last_estimated_bandwidth: 0,
+            decision_mode: DecisionMode::Default,
+            raw_target_history: VecDeque::new(),
+            recent_estimates: VecDeque::new(),
+            qoe_model: Box::new(LinearQoe::default()),
+            decision_log: Vec::new(),
+            decision_log_capacity: 0,
             bandwidth_window: Duration::from_secs(10),
+            time_bucket: None,
+            size_weighted_estimate: false,
+            decay_kernel: DecayKernel::Exponential,
+            steady_state_estimator: false,
             safety_factor: 0.8, // Use 80% of estimated bandwidth
+            startup_safety_factor: 0.8,
             buffer_panic_threshold: Duration::from_secs(3),
+            panic_factor: 0.3,
             buffer_seek_threshold: Duration::from_secs(45),
+            seek_threshold_ratio: None,
+            segments_in_seeking_mode: 0,
+            seek_decay_segments: 5,
             min_bandwidth_samples: 3,
+            min_bandwidth_floor: 1_000, // 8 kbps
+            bandwidth_ceiling: None,
+            network_class: NetworkClass::Unknown,
+            initial_bandwidth_estimate: None,
+            next_segment_deadline: None,
+            next_segment_size_hint: None,
+            deadline_margin: Duration::from_millis(500),
+            deadline_misses: 0,
+            empirical_segment_sizes: true,
+            upswitch_buffer_safety: false,
+            adapt_only_after_min_buffer: false,
+            min_switch_interval: None,
+            max_startup_quality: None,
+            min_upswitch_samples: 0,
+            panic_bandwidth_floor: false,
+            latency_mode: LatencyMode::Normal,
+            target_warmup: Duration::from_secs(0),
+            boundary_policy: BoundaryPolicy::StrictFit,
+            selection_preference: SelectionPreference::Bitrate,
+            max_upswitch_steps_when_full: 1,
+            selection_hysteresis: 0.0,
+            upswitch_margin: 1.0,
+            upswitch_margin_per_quality,
+            freeze_timeout: Duration::from_secs(2),
+            rebuffer_grace: Duration::from_secs(0),
+            last_playhead: None,
+            frozen: false,
+            min_buffer_per_quality,
+            utilization_layers: UtilizationLayers::default(),
+            buffer_zone_listener: None,
+            ladder_gap_threshold: 2.0,
+            supported_codecs: None,
+            viewport: None,
         }
     }
 
-    pub fn record_segment_download(
-        &mut self,
-        segment_size: u32,
-        download_duration: Duration,
-        segment_duration: Duration,
-    ) {
-        let now: Instant = Instant::now();
-        
-        let bandwidth: u32 = if download_duration.as_millis() > 0 {
-            (segment_size as f64 / download_duration.as_secs_f64()) as u32
-        } else {
-            u32::MAX // Instantaneous download
-        };
-        
-        self.bandwidth_history.push_back((now, bandwidth));
-        
-        self.cleanup_bandwidth_history(now);
-        
-        let segment_info: SegmentInfo = SegmentInfo {
-            quality_level: self.current_quality,
-            size_bytes: segment_size,
-            duration: segment_duration,
-            download_time: download_duration,
-        };
-        
-        self.segment_history.push_back(segment_info);
-        if self.segment_history.len() > 50 {
-            self.segment_history.pop_front();
-        }
-        
-        self.buffer_state.current_level += segment_duration;
-        if self.buffer_state.current_level > self.buffer_state.max_level {
-            self.buffer_state.current_level = self.buffer_state.max_level;
-        }
+    // The session start is re-anchored on the new clock's timeline
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+        self.session_start = self.clock.now();
     }
 
-    pub fn update_buffer_consumption(&mut self, consumed_duration: Duration) {
-        if self.buffer_state.current_level >= consumed_duration {
-            self.buffer_state.current_level -= consumed_duration;
-        } else {
-            self.buffer_state.current_level = Duration::from_secs(0);
-        }
+    pub fn set_session_id(&mut self, session_id: String) {
+        self.session_id = session_id;
     }
 
-    pub fn get_next_quality(&mut self) -> usize {
-        let estimated_bandwidth: u32 = self.estimate_bandwidth();
-        
-        // Buffer-based adaptation
-        let buffer_factor: f64 = self.calculate_buffer_factor();
-        
-        // Apply buffer factor to bandwidth estimate
-        let effective_bandwidth: u32 = (estimated_bandwidth as f64 * buffer_factor) as u32;
-        
-        // Find the highest quality that fits within the effective bandwidth
-        let target_quality: usize = self.find_suitable_quality(effective_bandwidth);
-        
-        // Apply smoothing to avoid oscillations
-        let next_quality: usize = self.apply_quality_smoothing(target_quality);
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    pub fn session_start(&self) -> Instant {
+        self.session_start
+    }
+
+    pub fn session_elapsed(&self) -> Duration {
+        self.clock.now().saturating_duration_since(self.session_start)
+    }
+
+    // Starts a new playback session with a fresh id: all measurements, counters
+    // and playback state are cleared, configuration is kept
+    pub fn reset(&mut self) {
+        self.session_id = generate_session_id();
+        self.session_start = self.clock.now();
         
-        self.current_quality = next_quality;
-        next_quality
+        self.current_quality = self.quality_levels.len() / 2;
+        self.adaptation_started = false;
+        self.startup_established = false;
+        self.adaptation_paused = false;
+        self.pending_probe = false;
+        self.exploration_probes = 0;
+        self.exploration_successes = 0;
+        self.bandwidth_history.clear();
+        self.lifetime_bandwidth_sum = 0;
+        self.lifetime_bandwidth_samples = 0;
+        self.transferred_bytes = 0;
+        self.peak_bandwidth = 0;
+        self.first_sample = None;
+        self.steady_samples = 0;
+        self.decisions_since_first_sample = 0;
+        self.buffer_state.current_level = Duration::from_secs(0);
+        self.last_buffer_zone = self.buffer_zone();
+        self.segment_history.clear();
+        self.content_stats.clear();
+        self.time_at_quality = vec![Duration::from_secs(0); self.quality_levels.len()];
+        self.rebuffer_count = 0;
+        self.rebuffer_time = Duration::from_secs(0);
+        self.rebuffer_events.clear();
+        self.consumption_events.clear();
+        self.session_utility = 0.0;
+        self.session_switch_utility = 0.0;
+        self.in_rebuffer = false;
+        self.current_stall = Duration::from_secs(0);
+        self.stall_counted = false;
+        self.segments_since_rebuffer = None;
+        self.up_switches = 0;
+        self.down_switches = 0;
+        self.quality_entered_at = None;
+        self.last_switch_at = None;
+        self.last_stable_quality = None;
+        self.last_estimated_bandwidth = 0;
+        self.recent_estimates.clear();
+        self.raw_target_history.clear();
+        self.decision_log.clear();
+        self.segments_in_seeking_mode = 0;
+        self.next_segment_deadline = None;
+        self.next_segment_size_hint = None;
+        self.deadline_misses = 0;
+        self.last_playhead = None;
+        self.frozen = false;
     }
 
-    fn estimate_bandwidth(&self) -> u32 {
-        if self.bandwidth_history.len() < self.min_bandwidth_samples {
-            return self.quality_levels[self.current_quality].bitrate / 8; // Convert to bytes/sec
+    pub fn with_config(quality_levels: Vec<QualityLevel>, config: &StreamerConfig) -> Result<Self, ConfigError> {
+        if quality_levels.is_empty() {
+            return Err(ConfigError::EmptyLadder);
         }
+        let mut streamer: AdaptiveBitrateStreamer = AdaptiveBitrateStreamer::new(quality_levels);
+        streamer.apply_config(config)?;
+        Ok(streamer)
+    }
 
-        let harmonic_mean: u32 = self.calculate_harmonic_mean_bandwidth();
-        let weighted_average: u32 = self.calculate_weighted_average_bandwidth();
-        let percentile_estimate: u32 = self.calculate_percentile_bandwidth(0.2); // 20th percentile for conservative estimate
-        
-        harmonic_mean.min(weighted_average).min(percentile_estimate)
+    pub fn config(&self) -> StreamerConfig {
+        StreamerConfig {
+            safety_factor: self.safety_factor,
+            startup_safety_factor: self.startup_safety_factor,
+            panic_factor: self.panic_factor,
+            panic_threshold: self.buffer_panic_threshold,
+            min_bandwidth_samples: self.min_bandwidth_samples,
+            upswitch_margin: self.upswitch_margin,
+            selection_hysteresis: self.selection_hysteresis,
+            decision_mode: self.decision_mode,
+            utilization_layers: self.utilization_layers,
+        }
     }
 
-    fn calculate_harmonic_mean_bandwidth(&self) -> u32 {
-        let sum_reciprocals: f64 = self.bandwidth_history
-            .iter()
-            .map(|(_, bw)| 1.0 / (*bw as f64).max(1.0))
-            .sum();
+    // A rejected config leaves the streamer as it was
+    pub fn apply_config(&mut self, config: &StreamerConfig) -> Result<(), ConfigError> {
+        config.validate()?;
+        self.assign_config(config);
+        Ok(())
+    }
+
+    fn assign_config(&mut self, config: &StreamerConfig) {
+        self.safety_factor = config.safety_factor;
+        self.startup_safety_factor = config.startup_safety_factor;
+        self.panic_factor = config.panic_factor;
+        self.buffer_panic_threshold = config.panic_threshold;
+        self.min_bandwidth_samples = config.min_bandwidth_samples;
+        self.upswitch_margin = config.upswitch_margin;
+        self.selection_hysteresis = config.selection_hysteresis;
+        self.decision_mode = config.decision_mode;
+        self.utilization_layers = config.utilization_layers;
+    }
+
+    // Re-runs the decisions over the recorded segments and playback under
+    // `config`, on a scratch streamer. Each segment keeps its recorded
+    // throughput, rescaled to the quality the replay chose. Everything outside
+    // StreamerConfig starts from defaults, apart from the buffer levels.
+    // Fails, like `apply_config`, on a config `StreamerConfig::validate` rejects.
+    pub fn replay_with(&self, config: StreamerConfig) -> Result<Vec<QualityDecision>, ConfigError> {
+        let mut replay: AdaptiveBitrateStreamer = AdaptiveBitrateStreamer::with_config(self.quality_levels.clone(), &config)?;
+        replay.buffer_state.target_level = self.buffer_state.target_level;
+        replay.buffer_state.min_level = self.buffer_state.min_level;
+        replay.buffer_state.max_level = self.buffer_state.max_level;
         
-        (self.bandwidth_history.len() as f64 / sum_reciprocals) as u32
+        let segments: Vec<&SegmentInfo> = self.segment_history.iter().collect();
+        Ok(replay.replay_segments(&segments, Some(&self.consumption_events)))
     }
 
-    fn calculate_weighted_average_bandwidth(&self) -> u32 {
-        let now: Instant = Instant::now();
-        let mut weighted_sum: f64 = 0.0;
-        let mut weight_sum: f64 = 0.0;
+    // Feeds `segments` through this (scratch) streamer, each fetched at its
+    // recorded throughput but sized for the quality this streamer picked.
+    // With recorded playback, the clock follows the recorded timestamps and
+    // drains; without, segments are fetched back to back and playback drains
+    // the buffer for as long as each download takes.
+    fn replay_segments(
+        &mut self,
+        segments: &[&SegmentInfo],
+        recorded_playback: Option<&VecDeque<(Instant, Duration)>>,
+    ) -> Vec<QualityDecision> {
+        let Some(first_segment) = segments.first() else {
+            return Vec::new();
+        };
+        let now: Rc<Cell<Instant>> = Rc::new(Cell::new(first_segment.recorded_at));
+        self.set_clock(Box::new(ReplayClock { now: Rc::clone(&now) }));
         
-        for (timestamp, bandwidth) in &self.bandwidth_history {
-            let age = now.duration_since(*timestamp).as_secs_f64();
-            let weight = (-age / self.bandwidth_window.as_secs_f64()).exp();
+        let mut consumption = recorded_playback.into_iter().flatten().peekable();
+        let mut decisions: Vec<QualityDecision> = Vec::with_capacity(segments.len());
+        for (i, segment) in segments.iter().enumerate() {
+            let scale: f64 = self.quality_levels[self.current_quality].bitrate as f64 / segment.bitrate.max(1) as f64;
+            let segment_size: u32 = (segment.size_bytes as f64 * scale) as u32;
+            let download_duration: Duration = segment.download_time.mul_f64(scale);
             
-            weighted_sum += *bandwidth as f64 * weight;
-            weight_sum += weight;
+            if recorded_playback.is_none() {
+                now.set(now.get() + download_duration);
+                self.record_download_with_concurrent_playback(
+                    segment_size,
+                    download_duration,
+                    segment.duration,
+                    download_duration,
+                );
+                decisions.push(self.get_next_quality_detailed());
+                continue;
+            }
+            
+            now.set(segment.recorded_at);
+            self.record_segment_download(segment_size, download_duration, segment.duration);
+            
+            // Playback up to the next download belongs to this cycle
+            let next_download_at: Option<Instant> = segments.get(i + 1).map(|next| next.recorded_at);
+            while let Some(&&(consumed_at, drained)) = consumption.peek() {
+                if next_download_at.is_some_and(|next| consumed_at >= next) {
+                    break;
+                }
+                now.set(consumed_at.max(segment.recorded_at));
+                self.update_buffer_consumption(drained);
+                consumption.next();
+            }
+            decisions.push(self.get_next_quality_detailed());
         }
         
-        if weight_sum > 0.0 {
-            (weighted_sum / weight_sum) as u32
-        } else {
-            0
-        }
+        decisions
     }
 
-    fn calculate_percentile_bandwidth(&self, percentile: f64) -> u32 {
-        let mut bandwidths: Vec<u32> = self.bandwidth_history
-            .iter()
-            .map(|(_, bw)| *bw)
-            .collect();
-        
-        if bandwidths.is_empty() {
-            return 0;
+    pub fn set_rng(&mut self, rng: Box<dyn RngSource>) {
+        self.rng = rng;
+    }
+
+    pub fn set_panic_factor(&mut self, factor: f64) -> Result<(), ConfigError> {
+        if !(factor > 0.0 && factor < 1.0) {
+            return Err(ConfigError::OutOfRange { parameter: "panic_factor", value: factor });
         }
-        
-        bandwidths.sort_unstable();
-        let index: usize = (bandwidths.len() as f64 * percentile) as usize;
-        bandwidths[index.min(bandwidths.len() - 1)]
+        self.panic_factor = factor;
+        Ok(())
     }
 
-    fn calculate_buffer_factor(&self) -> f64 {
-        let current_buffer: f64 = self.buffer_state.current_level.as_secs_f64();
-        let target_buffer: f64 = self.buffer_state.target_level.as_secs_f64();
-        let panic_threshold: f64 = self.buffer_panic_threshold.as_secs_f64();
-        let seek_threshold: f64 = self.buffer_seek_threshold.as_secs_f64();
+    // Seeds the startup estimate and, while still in startup, the current quality
+    pub fn set_network_class(&mut self, network_class: NetworkClass) {
+        self.network_class = network_class;
         
-        if current_buffer < panic_threshold {
-            // Buffer panic: be very conservative
-            0.3
-        } else if current_buffer < target_buffer {
-            // Below target: be somewhat conservative
-            0.6 + 0.3 * (current_buffer / target_buffer)
-        } else if current_buffer > seek_threshold {
-            // Buffer seeking: can be more aggressive
-            1.5
-        } else {
-            // Normal operation
-            1.0
+        if !self.has_confident_estimate() {
+            self.current_quality = match network_class.initial_bandwidth_estimate() {
+                Some(bandwidth) => {
+                    self.highest_quality_within((bandwidth as f64 * self.safety_factor as f64) as u32)
+                }
+                None => self.quality_levels.len() / 2,
+            };
         }
     }
 
-    fn find_suitable_quality(&self, available_bandwidth: u32) -> usize {
-        let safe_bandwidth: u32 = (available_bandwidth as f64 * self.safety_factor as f64) as u32;
-        
-        for (i, quality) in self.quality_levels.iter().enumerate().rev() {
-            let required_bandwidth: u32 = quality.bitrate / 8; // Convert to bytes/sec
-            if required_bandwidth <= safe_bandwidth {
-                return i;
-            }
+    // Takes precedence over the network class seed until the first sample
+    // arrives, so the startup guess doesn't depend on the current rendition
+    pub fn set_initial_bandwidth_estimate(&mut self, bytes_per_second: u32) -> Result<(), ConfigError> {
+        if bytes_per_second == 0 {
+            return Err(ConfigError::OutOfRange { parameter: "initial_bandwidth_estimate", value: 0.0 });
         }
-        
-        0
+        self.initial_bandwidth_estimate = Some(bytes_per_second);
+        Ok(())
     }
 
-    fn apply_quality_smoothing(&self, target_quality: usize) -> usize {
-        let current = self.current_quality as i32;
-        let target = target_quality as i32;
-        let diff = target - current;
-        
-        // Limit quality changes to prevent oscillations
-        let max_change = if self.buffer_state.current_level < self.buffer_panic_threshold {
-            // In panic mode, allow immediate downgrade
-            if diff < 0 { diff } else { 1 }
-        } else {
-            // Normal operation: limit changes
-            diff.signum() * 1.min(diff.abs())
-        };
-        
-        ((current + max_change).max(0) as usize).min(self.quality_levels.len() - 1)
+    pub fn network_class(&self) -> NetworkClass {
+        self.network_class
     }
 
-    fn cleanup_bandwidth_history(&mut self, now: Instant) {
-        while let Some((timestamp, _)) = self.bandwidth_history.front() {
-            if now.duration_since(*timestamp) > self.bandwidth_window {
-                self.bandwidth_history.pop_front();
+    pub fn set_seek_threshold_ratio(&mut self, ratio: f64) -> Result<(), ConfigError> {
+        if !(ratio > 0.0 && ratio <= 1.0) {
+            return Err(ConfigError::OutOfRange { parameter: "seek_threshold_ratio", value: ratio });
+        }
+        self.seek_threshold_ratio = Some(ratio);
+        Ok(())
+    }
+
+    pub fn seek_threshold(&self) -> Duration {
+        match self.seek_threshold_ratio {
+            Some(ratio) => self.buffer_state.max_level.mul_f64(ratio),
+            None => self.buffer_seek_threshold,
+        }
+    }
+
+    pub fn set_bandwidth_window(&mut self, window: Duration) -> Result<(), ConfigError> {
+        if window.is_zero() {
+            return Err(ConfigError::OutOfRange { parameter: "bandwidth_window", value: 0.0 });
+        }
+        self.bandwidth_window = window;
+        self.cleanup_bandwidth_history(self.clock.now());
+        Ok(())
+    }
+
+    pub fn bandwidth_window(&self) -> Duration {
+        self.bandwidth_window
+    }
+
+    pub fn set_min_bandwidth_floor(&mut self, floor: u32) -> Result<(), ConfigError> {
+        if floor == 0 {
+            return Err(ConfigError::OutOfRange { parameter: "min_bandwidth_floor", value: 0.0 });
+        }
+        self.min_bandwidth_floor = floor;
+        Ok(())
+    }
+
+    // Throughput above a contractual or CDN cap is a measurement artifact, so
+    // the final estimate never exceeds it
+    pub fn set_bandwidth_ceiling(&mut self, ceiling: Option<u32>) -> Result<(), ConfigError> {
+        if ceiling == Some(0) {
+            return Err(ConfigError::OutOfRange { parameter: "bandwidth_ceiling", value: 0.0 });
+        }
+        self.bandwidth_ceiling = ceiling;
+        Ok(())
+    }
+
+    pub fn set_utilization_layers(&mut self, layers: UtilizationLayers) {
+        self.utilization_layers = layers;
+    }
+
+    pub fn utilization_layers(&self) -> UtilizationLayers {
+        self.utilization_layers
+    }
+
+    // Replaces the ladder, keeping the closest bitrate to what is playing now.
+    // Per-quality settings are index based and are reset.
+    pub fn update_quality_levels(&mut self, quality_levels: Vec<QualityLevel>) -> Result<(), ConfigError> {
+        if quality_levels.is_empty() {
+            return Err(ConfigError::EmptyLadder);
+        }
+        
+        // Follow the same rendition through a reorder; fall back to the
+        // nearest bitrate once it is gone
+        let current_level: QualityLevel = self.quality_levels[self.current_quality].clone();
+        self.quality_levels = quality_levels;
+        self.utilities = compute_utilities(&self.quality_levels);
+        self.current_quality = match self.quality_levels.iter().position(|quality| *quality == current_level) {
+            Some(index) => index,
+            None => self.closest_quality_to_bitrate(current_level.bitrate),
+        };
+        
+        self.min_buffer_per_quality = vec![None; self.quality_levels.len()];
+        self.upswitch_margin_per_quality = vec![None; self.quality_levels.len()];
+        self.max_startup_quality = None;
+        self.raw_target_history.clear();
+        self.last_stable_quality = None;
+        self.time_at_quality.resize(self.quality_levels.len(), Duration::from_secs(0));
+        Ok(())
+    }
+
+    pub fn validate_ladder_quality(&self) -> Vec<LadderWarning> {
+        self.quality_levels
+            .windows(2)
+            .enumerate()
+            .filter_map(|(i, pair)| {
+                let lower_bits_per_pixel: f64 = pair[0].bits_per_pixel(LADDER_CHECK_FPS);
+                let bits_per_pixel: f64 = pair[1].bits_per_pixel(LADDER_CHECK_FPS);
+                if bits_per_pixel < lower_bits_per_pixel {
+                    Some(LadderWarning::BitsPerPixelInversion {
+                        index: i + 1,
+                        bits_per_pixel,
+                        lower_bits_per_pixel,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn set_ladder_gap_threshold(&mut self, threshold: f64) -> Result<(), ConfigError> {
+        if !(threshold > 1.0 && threshold.is_finite()) {
+            return Err(ConfigError::OutOfRange { parameter: "ladder_gap_threshold", value: threshold });
+        }
+        self.ladder_gap_threshold = threshold;
+        Ok(())
+    }
+
+    pub fn ladder_gap_analysis(&self) -> Vec<LadderGap> {
+        self.quality_levels
+            .windows(2)
+            .enumerate()
+            .filter_map(|(i, pair)| {
+                let bitrate_ratio: f64 = pair[1].bitrate as f64 / pair[0].bitrate.max(1) as f64;
+                if bitrate_ratio > self.ladder_gap_threshold {
+                    Some(LadderGap {
+                        lower_index: i,
+                        upper_index: i + 1,
+                        bitrate_ratio,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn quality_utility(&self, index: usize) -> f64 {
+        self.utilities.get(index).copied().unwrap_or(0.0)
+    }
+
+    // Nearest bitrate match; ties resolve to the lower bitrate
+    pub fn closest_quality_to_bitrate(&self, target_bps: u32) -> usize {
+        self.quality_levels
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, quality)| (quality.bitrate.abs_diff(target_bps), quality.bitrate))
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+
+    pub fn set_min_buffer_for_quality(
+        &mut self,
+        index: usize,
+        min_buffer: Duration,
+    ) -> Result<(), ConfigError> {
+        let entry = self.min_buffer_per_quality
+            .get_mut(index)
+            .ok_or(ConfigError::QualityIndexOutOfRange(index))?;
+        *entry = Some(min_buffer);
+        Ok(())
+    }
+
+    // Unset entries report the global min_level, but only explicit entries
+    // keep a rendition from being selected
+    pub fn min_buffer_for_quality(&self, index: usize) -> Duration {
+        self.min_buffer_per_quality
+            .get(index)
+            .copied()
+            .flatten()
+            .unwrap_or(self.buffer_state.min_level)
+    }
+
+    // Returns true when the segment did not fit in the buffer; what happens to
+    // the buffer then depends on the `BufferFullPolicy`
+    pub fn record_segment_download(
+        &mut self,
+        segment_size: u32,
+        download_duration: Duration,
+        segment_duration: Duration,
+    ) -> bool {
+        self.record_segment_download_tagged(
+            segment_size,
+            download_duration,
+            segment_duration,
+            ContentType::Main,
+        )
+    }
+
+    pub fn record_segment_download_tagged(
+        &mut self,
+        segment_size: u32,
+        download_duration: Duration,
+        segment_duration: Duration,
+        content_tag: ContentType,
+    ) -> bool {
+        self.record_download(segment_size, segment_size, download_duration, segment_duration, content_tag)
+    }
+
+    // Playback keeps draining the buffer while a download is in flight, so the
+    // drain lands first and the segment is appended once it completes. A drain
+    // beyond what was buffered counts as a rebuffer that the arrival ends.
+    pub fn record_download_with_concurrent_playback(
+        &mut self,
+        segment_size: u32,
+        download_duration: Duration,
+        segment_duration: Duration,
+        playback_during: Duration,
+    ) -> bool {
+        self.update_buffer_consumption(playback_during);
+        self.record_segment_download(segment_size, download_duration, segment_duration)
+    }
+
+    // Same as `record_segment_download`, annotated with the segment's scene
+    // complexity so upcoming sizes can be anticipated; see `complexity_factor`
+    pub fn record_segment_download_with_complexity(
+        &mut self,
+        segment_size: u32,
+        download_duration: Duration,
+        segment_duration: Duration,
+        complexity: f32,
+    ) -> bool {
+        let overflowed: bool = self.record_segment_download(segment_size, download_duration, segment_duration);
+        // A rejected segment isn't in the history, so there's nothing to annotate
+        let rejected: bool = overflowed && self.buffer_full_policy == BufferFullPolicy::RejectDownload;
+        if !rejected && let Some(segment) = self.segment_history.back_mut() {
+            segment.complexity = Some(complexity);
+        }
+        overflowed
+    }
+
+    // Recent complexity relative to the session's typical complexity: VBR
+    // sizes track scene content, so a run of complex segments predicts
+    // larger ones next. 1.0 without annotations.
+    pub fn complexity_factor(&self) -> f64 {
+        const RECENT_COMPLEXITY_SEGMENTS: usize = 3;
+        
+        let annotated: Vec<f64> = self.segment_history
+            .iter()
+            .filter_map(|segment| segment.complexity)
+            .map(|complexity| complexity as f64)
+            .collect();
+        if annotated.is_empty() {
+            return 1.0;
+        }
+        
+        let recent: &[f64] = &annotated[annotated.len().saturating_sub(RECENT_COMPLEXITY_SEGMENTS)..];
+        let recent_mean: f64 = recent.iter().sum::<f64>() / recent.len() as f64;
+        let overall_mean: f64 = annotated.iter().sum::<f64>() / annotated.len() as f64;
+        finite_or(recent_mean / overall_mean, 1.0).max(0.0)
+    }
+
+    // For players that can tell application bytes from on-wire bytes: the
+    // bandwidth sample and segment size use `useful_bytes`, so the estimate
+    // tracks goodput rather than raw transfer on lossy links
+    pub fn record_segment_download_goodput(
+        &mut self,
+        transferred_bytes: u32,
+        useful_bytes: u32,
+        download_duration: Duration,
+        segment_duration: Duration,
+    ) -> bool {
+        self.record_download(useful_bytes, transferred_bytes, download_duration, segment_duration, ContentType::Main)
+    }
+
+    fn record_download(
+        &mut self,
+        segment_size: u32,
+        transferred_bytes: u32,
+        download_duration: Duration,
+        segment_duration: Duration,
+        content_tag: ContentType,
+    ) -> bool {
+        let now: Instant = self.clock.now();
+        self.transferred_bytes += transferred_bytes as u64;
+        
+        if self.pending_probe {
+            self.pending_probe = false;
+            if download_duration <= segment_duration {
+                self.exploration_successes += 1;
+            }
+        }
+        
+        let bandwidth: Option<u32> = if download_duration.as_millis() > 0 {
+            Some((segment_size as f64 / download_duration.as_secs_f64()) as u32)
+        } else {
+            match self.zero_duration_policy {
+                ZeroDurationPolicy::Clamp => Some((segment_size as f64 / 0.001) as u32),
+                ZeroDurationPolicy::Ignore => None,
+                ZeroDurationPolicy::MaxBandwidth => Some(u32::MAX), // Instantaneous download
+            }
+        };
+        
+        if let Some(bandwidth) = bandwidth {
+            self.bandwidth_history.push_back((now, bandwidth));
+            self.lifetime_bandwidth_sum += bandwidth as u64;
+            self.lifetime_bandwidth_samples += 1;
+            self.peak_bandwidth = self.peak_bandwidth.max(bandwidth);
+            if self.lifetime_bandwidth_samples == 1 {
+                self.first_sample = Some((now, bandwidth));
+            }
+        }
+        
+        self.cleanup_bandwidth_history(now);
+        if bandwidth.is_some() {
+            if self.bandwidth_cv() < STABLE_BANDWIDTH_CV {
+                self.steady_samples = self.steady_samples.saturating_add(1);
             } else {
-                break;
+                self.steady_samples = 0;
+            }
+        }
+        
+        // A rejected segment never reaches the buffer, so it stays out of the
+        // segment history and content stats; the bandwidth sample still counts
+        let overflowed: bool = self.buffer_state.current_level + segment_duration > self.buffer_state.max_level;
+        if overflowed && self.buffer_full_policy == BufferFullPolicy::RejectDownload {
+            return true;
+        }
+        
+        let segment_info: SegmentInfo = SegmentInfo {
+            quality_level: self.current_quality,
+            bitrate: self.quality_levels[self.current_quality].bitrate,
+            size_bytes: segment_size,
+            duration: segment_duration,
+            download_time: download_duration,
+            content_type: content_tag,
+            recorded_at: now,
+            complexity: None,
+        };
+        
+        let stats: &mut ContentStats = self.content_stats.entry(content_tag).or_default();
+        stats.segment_count += 1;
+        stats.total_bytes += segment_size as u64;
+        stats.total_duration += segment_duration;
+        stats.total_download_time += download_duration;
+        self.time_at_quality[self.current_quality] += segment_duration;
+        
+        let utility: f64 = self.quality_utility(self.current_quality);
+        self.session_utility += utility * segment_duration.as_secs_f64();
+        if let Some(previous) = self.segment_history.back() {
+            self.session_switch_utility += (utility - self.quality_utility(previous.quality_level)).abs();
+        }
+        
+        self.segment_history.push_back(segment_info);
+        if self.segment_history.len() > SEGMENT_HISTORY_CAPACITY {
+            self.segment_history.pop_front();
+        }
+        
+        self.in_rebuffer = false;
+        if let Some(segments) = self.segments_since_rebuffer.as_mut() {
+            *segments = segments.saturating_add(1);
+        }
+        if overflowed {
+            match self.buffer_full_policy {
+                BufferFullPolicy::Clamp => self.buffer_state.current_level = self.buffer_state.max_level,
+                BufferFullPolicy::RejectDownload => unreachable!("rejected overflows return early"),
+                BufferFullPolicy::IncreaseMax => {
+                    self.buffer_state.current_level += segment_duration;
+                    self.buffer_state.max_level = self.buffer_state.current_level;
+                }
+            }
+        } else {
+            self.buffer_state.current_level += segment_duration;
+        }
+        self.notify_buffer_zone();
+        
+        if let Some(bandwidth) = bandwidth {
+            self.update_seeking_mode(bandwidth);
+        }
+        overflowed
+    }
+
+    pub fn set_buffer_full_policy(&mut self, policy: BufferFullPolicy) {
+        self.buffer_full_policy = policy;
+    }
+
+    pub fn set_zero_duration_policy(&mut self, policy: ZeroDurationPolicy) {
+        self.zero_duration_policy = policy;
+    }
+
+    pub fn set_first_sample_policy(&mut self, policy: FirstSamplePolicy) {
+        self.first_sample_policy = policy;
+    }
+
+    fn excluded_first_sample(&self) -> Option<(Instant, u32)> {
+        match self.first_sample_policy {
+            FirstSamplePolicy::Include => None,
+            FirstSamplePolicy::Exclude { decisions } if self.decisions_since_first_sample < decisions => {
+                self.first_sample
+            }
+            FirstSamplePolicy::Exclude { .. } => None,
+        }
+    }
+
+    // The aggressive seeking factor only holds while throughput keeps confirming
+    // the headroom; otherwise it ramps back to 1.0 over `seek_decay_segments`
+    fn update_seeking_mode(&mut self, bandwidth: u32) {
+        if self.buffer_state.current_level <= self.seek_threshold() {
+            self.segments_in_seeking_mode = 0;
+            return;
+        }
+        
+        let required_bandwidth: f64 = bitrate_to_byte_rate(self.quality_levels[self.current_quality].bitrate) as f64;
+        if bandwidth as f64 >= required_bandwidth * SEEKING_BUFFER_FACTOR {
+            self.segments_in_seeking_mode = 0;
+        } else {
+            self.segments_in_seeking_mode = self.segments_in_seeking_mode.saturating_add(1);
+        }
+    }
+
+    fn seeking_buffer_factor(&self) -> f64 {
+        if self.seek_decay_segments == 0 {
+            return SEEKING_BUFFER_FACTOR;
+        }
+        
+        let decay: f64 = self.segments_in_seeking_mode.min(self.seek_decay_segments) as f64
+            / self.seek_decay_segments as f64;
+        SEEKING_BUFFER_FACTOR - (SEEKING_BUFFER_FACTOR - 1.0) * decay
+    }
+
+    // Live variant: `deadline` is the wall-clock availability deadline of the
+    // segment just downloaded. The next segment's deadline follows one segment
+    // duration later.
+    pub fn record_segment_download_with_deadline(
+        &mut self,
+        segment_size: u32,
+        download_duration: Duration,
+        segment_duration: Duration,
+        deadline: Option<Instant>,
+    ) -> bool {
+        let overflowed: bool = self.record_segment_download(segment_size, download_duration, segment_duration);
+        
+        match deadline {
+            Some(deadline) => {
+                if self.clock.now() > deadline {
+                    self.deadline_misses += 1;
+                }
+                self.next_segment_deadline = Some(deadline + segment_duration);
+            }
+            None => self.next_segment_deadline = None,
+        }
+        
+        overflowed
+    }
+
+    pub fn set_next_segment_deadline(&mut self, deadline: Option<Instant>) {
+        self.next_segment_deadline = deadline;
+    }
+
+    pub fn set_deadline_margin(&mut self, margin: Duration) {
+        self.deadline_margin = margin;
+    }
+
+    pub fn deadline_misses(&self) -> u32 {
+        self.deadline_misses
+    }
+
+    pub fn set_empirical_segment_sizes(&mut self, enabled: bool) {
+        self.empirical_segment_sizes = enabled;
+    }
+
+    // Hold the startup quality until the buffer first reaches min_level; from
+    // then on adaptation runs normally, even if the buffer drops again
+    pub fn set_adapt_only_after_min_buffer(&mut self, enabled: bool) {
+        self.adapt_only_after_min_buffer = enabled;
+    }
+
+    // Wall-clock cap of one switch per `per`, independent of segment length.
+    // Panic downgrades and live deadlines are never held back.
+    pub fn set_max_switch_rate(&mut self, per: Duration) {
+        self.min_switch_interval = Some(per);
+    }
+
+    // Ceiling on early decisions, lifted for good once the estimate is
+    // confident and the buffer has reached min_level
+    pub fn set_max_startup_quality(&mut self, quality: Option<usize>) -> Result<(), ConfigError> {
+        if let Some(index) = quality
+            && index >= self.quality_levels.len()
+        {
+            return Err(ConfigError::QualityIndexOutOfRange(index));
+        }
+        self.max_startup_quality = quality;
+        Ok(())
+    }
+
+    fn startup_quality_cap(&mut self) -> Option<usize> {
+        if !self.startup_established && self.has_confident_estimate() && self.is_buffer_healthy() {
+            self.startup_established = true;
+        }
+        self.max_startup_quality.filter(|_| !self.startup_established)
+    }
+
+    fn switch_rate_limited(&self) -> bool {
+        let (Some(interval), Some(last_switch_at)) = (self.min_switch_interval, self.last_switch_at) else {
+            return false;
+        };
+        self.clock.now().saturating_duration_since(last_switch_at) < interval
+    }
+
+    // Only step up when the higher rendition can be fetched before the buffer runs dry
+    pub fn set_upswitch_buffer_safety(&mut self, enabled: bool) {
+        self.upswitch_buffer_safety = enabled;
+    }
+
+    // A margin of 1.5 means stepping up needs 1.5x the level's bitrate in
+    // effective bandwidth; 1.0 (the default) adds no headroom
+    pub fn set_upswitch_margin(&mut self, margin: f64) -> Result<(), ConfigError> {
+        if !(margin >= 1.0 && margin.is_finite()) {
+            return Err(ConfigError::OutOfRange { parameter: "upswitch_margin", value: margin });
+        }
+        self.upswitch_margin = margin;
+        Ok(())
+    }
+
+    pub fn set_upswitch_margin_for(&mut self, index: usize, margin: f64) -> Result<(), ConfigError> {
+        if !(margin >= 1.0 && margin.is_finite()) {
+            return Err(ConfigError::OutOfRange { parameter: "upswitch_margin", value: margin });
+        }
+        let entry = self.upswitch_margin_per_quality
+            .get_mut(index)
+            .ok_or(ConfigError::QualityIndexOutOfRange(index))?;
+        *entry = Some(margin);
+        Ok(())
+    }
+
+    pub fn upswitch_margin_for(&self, index: usize) -> f64 {
+        self.upswitch_margin_per_quality
+            .get(index)
+            .copied()
+            .flatten()
+            .unwrap_or(self.upswitch_margin)
+    }
+
+    // Called by the player on each render tick. A playhead that stops advancing
+    // while there is buffer to play is a decode stall, not a rebuffer.
+    pub fn report_playback_progress(&mut self, playhead: Duration) {
+        let now: Instant = self.clock.now();
+        
+        match self.last_playhead {
+            Some((last_playhead, _)) if playhead > last_playhead => {
+                self.last_playhead = Some((playhead, now));
+                self.frozen = false;
             }
+            Some((last_playhead, last_progress)) => {
+                if self.buffer_state.current_level.is_zero() {
+                    // Waiting on the network: restart the watchdog
+                    self.last_playhead = Some((last_playhead, now));
+                    self.frozen = false;
+                } else if now.duration_since(last_progress) > self.freeze_timeout {
+                    self.frozen = true;
+                }
+            }
+            None => self.last_playhead = Some((playhead, now)),
+        }
+    }
+
+    // Stalls no longer than `grace` in total still add to rebuffer_time but are
+    // too brief to count as a user-visible rebuffer
+    pub fn set_rebuffer_grace(&mut self, grace: Duration) {
+        self.rebuffer_grace = grace;
+    }
+
+    pub fn set_freeze_timeout(&mut self, timeout: Duration) {
+        self.freeze_timeout = timeout;
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    // Warm restart from a prior session: each sample is (age, bytes_per_second)
+    // with the age measured back from now. Samples outside the window are dropped.
+    pub fn seed_bandwidth_history(&mut self, samples: &[(Duration, u32)]) {
+        let now: Instant = self.clock.now();
+        
+        for &(age, bandwidth) in samples {
+            if let Some(timestamp) = now.checked_sub(age) {
+                self.bandwidth_history.push_back((timestamp, bandwidth));
+            }
+        }
+        self.bandwidth_history.make_contiguous().sort_by_key(|(timestamp, _)| *timestamp);
+        
+        self.cleanup_bandwidth_history(now);
+    }
+
+    // Returns how much of the consumed playback had no buffer behind it, zero
+    // when the buffer covered it all
+    pub fn update_buffer_consumption(&mut self, consumed_duration: Duration) -> Duration {
+        let drained: Duration = consumed_duration.min(self.buffer_state.current_level);
+        self.consumption_events.push_back((self.clock.now(), drained));
+        if self.consumption_events.len() > CONSUMPTION_EVENT_CAPACITY {
+            self.consumption_events.pop_front();
+        }
+        
+        let deficit: Duration = if self.buffer_state.current_level >= consumed_duration {
+            self.buffer_state.current_level -= consumed_duration;
+            Duration::ZERO
+        } else {
+            // Playback outran the buffer: count one rebuffer per stall, once
+            // it has lasted longer than the grace period
+            if !self.in_rebuffer {
+                self.in_rebuffer = true;
+                self.current_stall = Duration::from_secs(0);
+                self.stall_counted = false;
+            }
+            let stall: Duration = consumed_duration - self.buffer_state.current_level;
+            self.current_stall += stall;
+            if !self.stall_counted && self.current_stall > self.rebuffer_grace {
+                self.rebuffer_count += 1;
+                self.stall_counted = true;
+                self.segments_since_rebuffer = Some(0);
+            }
+            self.rebuffer_time += stall;
+            self.rebuffer_events.push_back((self.clock.now(), stall));
+            if self.rebuffer_events.len() > SEGMENT_HISTORY_CAPACITY {
+                self.rebuffer_events.pop_front();
+            }
+            self.buffer_state.current_level = Duration::from_secs(0);
+            stall
+        };
+        self.notify_buffer_zone();
+        deficit
+    }
+
+    pub fn buffer_zone(&self) -> BufferZone {
+        let level: Duration = self.buffer_state.current_level;
+        if level < self.effective_panic_threshold() {
+            BufferZone::Panic
+        } else if level < self.effective_target_level() {
+            BufferZone::BelowTarget
+        } else if level > self.seek_threshold() {
+            BufferZone::Seeking
+        } else {
+            BufferZone::Normal
+        }
+    }
+
+    // Called with (old, new) each time a buffer change crosses into another zone
+    pub fn on_buffer_state_change(&mut self, listener: Box<dyn FnMut(BufferZone, BufferZone)>) {
+        self.buffer_zone_listener = Some(listener);
+    }
+
+    fn notify_buffer_zone(&mut self) {
+        let zone: BufferZone = self.buffer_zone();
+        if zone == self.last_buffer_zone {
+            return;
+        }
+        
+        let previous_zone: BufferZone = std::mem::replace(&mut self.last_buffer_zone, zone);
+        if let Some(listener) = self.buffer_zone_listener.as_mut() {
+            listener(previous_zone, zone);
+        }
+    }
+
+    // One lockstep cycle: the finished download lands in the buffer first, then
+    // playback drains it, and the decision sees the resulting buffer
+    pub fn step(&mut self, download: SegmentDownload, consumed: Duration) -> QualityDecision {
+        self.record_segment_download(download.segment_size, download.download_duration, download.segment_duration);
+        self.update_buffer_consumption(consumed);
+        self.get_next_quality_detailed()
+    }
+
+    // Worst case for risk-averse fallback: selects on the slowest sample in the
+    // window instead of the blended estimate, without smoothing
+    pub fn conservative_quality(&self) -> usize {
+        let raw_estimate: u32 = match self.estimation_samples().iter().map(|(_, bandwidth)| *bandwidth).min() {
+            Some(slowest) => slowest,
+            None => self.raw_estimate_bandwidth(),
+        };
+        let effective_bandwidth: u32 = (self.finalize_estimate(raw_estimate) as f64 * self.effective_utilization()) as u32;
+        self.find_suitable_quality(effective_bandwidth)
+    }
+
+    // Holds the current quality, e.g. through an ad break, while downloads and
+    // playback keep feeding the estimator and buffer. Hard limits (deadline,
+    // live edge, startup cap, viewport, codec support) still apply.
+    pub fn pause_adaptation(&mut self) {
+        self.adaptation_paused = true;
+    }
+
+    pub fn resume_adaptation(&mut self) {
+        self.adaptation_paused = false;
+    }
+
+    pub fn is_adaptation_paused(&self) -> bool {
+        self.adaptation_paused
+    }
+
+    // Out-of-band check for when the buffer moved since the last decision: drops
+    // straight to the highest sustainable level, never switches up
+    pub fn revalidate_current_quality(&mut self) -> Option<usize> {
+        let effective_bandwidth: u32 = (self.estimate_bandwidth() as f64 * self.effective_utilization()) as u32;
+        let feasible_quality: usize = self.find_suitable_quality(effective_bandwidth);
+        if feasible_quality >= self.current_quality {
+            return None;
+        }
+        
+        self.commit_quality(feasible_quality);
+        Some(feasible_quality)
+    }
+
+    // Replays (segment_bytes, download_time, segment_duration, consumed_during)
+    // events one full cycle each, returning the decisions in order
+    pub fn run_trace(&mut self, events: &[(u32, Duration, Duration, Duration)]) -> Vec<QualityDecision> {
+        events
+            .iter()
+            .map(|&(segment_size, download_duration, segment_duration, consumed)| {
+                let download: SegmentDownload = SegmentDownload { segment_size, download_duration, segment_duration };
+                self.step(download, consumed)
+            })
+            .collect()
+    }
+
+    pub fn set_buffer_unit(&mut self, unit: BufferUnit) {
+        self.buffer_unit = unit;
+    }
+
+    pub fn buffer_unit(&self) -> BufferUnit {
+        self.buffer_unit
+    }
+
+    fn units_to_duration(&self, amount: f64) -> Duration {
+        match self.buffer_unit {
+            BufferUnit::Duration => Duration::from_secs_f64(amount.max(0.0)),
+            BufferUnit::Bytes => {
+                let byte_rate: u32 = bitrate_to_byte_rate(self.quality_levels[self.current_quality].bitrate).max(1);
+                Duration::from_secs_f64(amount.max(0.0) / byte_rate as f64)
+            }
+        }
+    }
+
+    fn duration_to_units(&self, duration: Duration) -> f64 {
+        match self.buffer_unit {
+            BufferUnit::Duration => duration.as_secs_f64(),
+            BufferUnit::Bytes => {
+                duration.as_secs_f64() * bitrate_to_byte_rate(self.quality_levels[self.current_quality].bitrate) as f64
+            }
+        }
+    }
+
+    // `update_buffer_consumption` with the amount in the configured `BufferUnit`
+    pub fn update_buffer_consumption_in_units(&mut self, consumed: f64) {
+        let consumed_duration: Duration = self.units_to_duration(consumed);
+        self.update_buffer_consumption(consumed_duration);
+    }
+
+    pub fn buffer_level_in_units(&self) -> f64 {
+        self.duration_to_units(self.buffer_state.current_level)
+    }
+
+    pub fn set_panic_threshold_in_units(&mut self, threshold: f64) {
+        self.buffer_panic_threshold = self.units_to_duration(threshold);
+    }
+
+    pub fn get_next_quality(&mut self) -> usize {
+        self.get_next_quality_detailed().quality
+    }
+
+    pub fn get_next_quality_detailed(&mut self) -> QualityDecision {
+        let previous_quality: usize = self.current_quality;
+        let estimated_bandwidth: u32 = self.estimate_bandwidth();
+        self.last_estimated_bandwidth = estimated_bandwidth;
+        let utilization: f64 = self.effective_utilization();
+        
+        // Apply buffer factor and safety factor to bandwidth estimate
+        let mut effective_bandwidth: u32 = (estimated_bandwidth as f64 * utilization) as u32;
+        if self.panic_bandwidth_floor && self.buffer_state.current_level < self.effective_panic_threshold() {
+            let lowest_quality: usize = self.snap_to_supported_quality(0);
+            effective_bandwidth = effective_bandwidth.max(bitrate_to_byte_rate(self.quality_levels[lowest_quality].bitrate));
+        }
+        
+        let (target_quality, mut next_quality) = match self.decision_mode {
+            DecisionMode::Default => {
+                // Find the highest quality that fits within the effective bandwidth
+                let target_quality: usize = self.find_suitable_quality(effective_bandwidth);
+                
+                // Apply smoothing to avoid oscillations
+                (target_quality, self.apply_quality_smoothing(target_quality))
+            }
+            DecisionMode::Mpc { horizon } => {
+                // The plan already weighs switching costs, so no step smoothing
+                let planned_quality: usize = self.plan_mpc_quality(horizon);
+                (planned_quality, planned_quality)
+            }
+            DecisionMode::ModeSmoothed { window } => {
+                let target_quality: usize = self.find_suitable_quality(effective_bandwidth);
+                (target_quality, self.mode_smoothed_quality(target_quality, window))
+            }
+            DecisionMode::EpsilonGreedy { .. } => {
+                let target_quality: usize = self.find_suitable_quality(effective_bandwidth);
+                (target_quality, self.apply_quality_smoothing(target_quality))
+            }
+            DecisionMode::Hybrid { throughput_weight } => {
+                let weight: f64 = throughput_weight.clamp(0.0, 1.0);
+                let throughput_quality: f64 = self.throughput_target_quality(estimated_bandwidth) as f64;
+                let buffer_quality: f64 = self.buffer_target_quality() as f64;
+                let target_quality: usize = (weight * throughput_quality + (1.0 - weight) * buffer_quality).round() as usize;
+                (target_quality, self.apply_quality_smoothing(target_quality))
+            }
+        };
+        // Only the smoothing step counts as a clamp; caps and probes below don't
+        let clamped_from: Option<usize> = if next_quality != target_quality { Some(target_quality) } else { None };
+        if self.adapt_only_after_min_buffer && !self.adaptation_started {
+            if self.is_buffer_healthy() {
+                self.adaptation_started = true;
+            } else {
+                next_quality = previous_quality;
+            }
+        }
+        if self.adaptation_paused {
+            next_quality = previous_quality;
+        }
+        
+        let mut upswitch_suppressed_by: Option<SuppressionReason> = None;
+        if next_quality > previous_quality {
+            let proposed_quality: usize = next_quality;
+            (next_quality, upswitch_suppressed_by) =
+                self.apply_upswitch_gates(previous_quality, proposed_quality, effective_bandwidth);
+            abr_trace!(
+                "up-switch {previous_quality} -> {proposed_quality} gated to {next_quality}, suppressed by {upswitch_suppressed_by:?}"
+            );
+        }
+        
+        // A probe is meant to exceed the budget, so it comes after the gates
+        if let DecisionMode::EpsilonGreedy { epsilon } = self.decision_mode
+            && !self.adaptation_paused
+        {
+            next_quality = self.explore_quality(next_quality, epsilon);
+        }
+        
+        let panic_downgrade: bool =
+            next_quality < previous_quality && self.buffer_state.current_level < self.effective_panic_threshold();
+        if panic_downgrade {
+            abr_debug!(
+                "panic downgrade {previous_quality} -> {next_quality} at buffer {:?}",
+                self.buffer_state.current_level
+            );
+        }
+        if next_quality != previous_quality && !panic_downgrade && self.switch_rate_limited() {
+            if next_quality > previous_quality {
+                upswitch_suppressed_by = upswitch_suppressed_by.or(Some(SuppressionReason::RateLimit));
+            }
+            next_quality = previous_quality;
+        }
+        
+        // A live deadline is a hard limit, even if it means dropping several levels
+        if let Some(deadline_quality) = self.highest_quality_within_deadline() {
+            if next_quality > previous_quality && deadline_quality < next_quality {
+                upswitch_suppressed_by = upswitch_suppressed_by.or(Some(SuppressionReason::Deadline));
+            }
+            next_quality = next_quality.min(deadline_quality);
+        }
+        
+        if let Some(live_edge_quality) = self.live_edge_quality_cap() {
+            if next_quality > previous_quality && live_edge_quality < next_quality {
+                upswitch_suppressed_by = upswitch_suppressed_by.or(Some(SuppressionReason::LiveEdge));
+            }
+            next_quality = next_quality.min(live_edge_quality);
+        }
+        
+        if let Some(startup_cap) = self.startup_quality_cap() {
+            if next_quality > previous_quality && startup_cap < next_quality {
+                upswitch_suppressed_by = upswitch_suppressed_by.or(Some(SuppressionReason::StartupCap));
+            }
+            next_quality = next_quality.min(startup_cap);
+        }
+        
+        // The viewport already bounds the selection, so compare against what the
+        // bandwidth alone would carry
+        if let Some(viewport_cap) = self.viewport_quality_cap() {
+            let bandwidth_quality: usize = self.highest_quality_within(effective_bandwidth);
+            if bandwidth_quality > previous_quality && viewport_cap < bandwidth_quality {
+                upswitch_suppressed_by = upswitch_suppressed_by.or(Some(SuppressionReason::ResolutionCap));
+            }
+            next_quality = next_quality.min(viewport_cap);
+        }
+        
+        // Never hand out a rendition the device can't decode. Not reported as a
+        // suppression: an undecodable rendition was never a candidate.
+        next_quality = self.snap_to_supported_quality(next_quality);
+        
+        // A size hint describes exactly one upcoming segment
+        self.next_segment_size_hint = None;
+        if self.first_sample.is_some() {
+            self.decisions_since_first_sample = self.decisions_since_first_sample.saturating_add(1);
+        }
+        self.recent_estimates.push_back(estimated_bandwidth);
+        if self.recent_estimates.len() > RECENT_SEGMENT_COUNT {
+            self.recent_estimates.pop_front();
+        }
+        self.commit_quality(next_quality);
+        
+        let decision: QualityDecision = QualityDecision {
+            quality: next_quality,
+            previous_quality,
+            target_quality,
+            estimated_bandwidth,
+            effective_bandwidth,
+            buffer_level: self.buffer_state.current_level,
+            clamped_from,
+            upswitch_suppressed_by,
+        };
+        abr_debug!(
+            "decision quality={} previous={} target={} estimate={} effective={} buffer={:?}",
+            decision.quality,
+            decision.previous_quality,
+            decision.target_quality,
+            decision.estimated_bandwidth,
+            decision.effective_bandwidth,
+            decision.buffer_level
+        );
+        
+        if self.decision_log_capacity > 0 {
+            let input: DecisionInput = DecisionInput {
+                current_quality: previous_quality,
+                estimated_bandwidth,
+                bandwidth_samples: self.bandwidth_history.len(),
+                buffer_level: self.buffer_state.current_level,
+                buffer_factor: self.calculate_buffer_factor(),
+                utilization,
+            };
+            self.log_decision(LoggedDecision { input, decision: decision.clone() });
+        }
+        
+        decision
+    }
+
+    fn log_decision(&mut self, entry: LoggedDecision) {
+        self.decision_log.push(entry);
+        if self.decision_log.len() > self.decision_log_capacity {
+            let excess: usize = self.decision_log.len() - self.decision_log_capacity;
+            self.decision_log.drain(..excess);
+        }
+    }
+
+    pub fn set_decision_log_capacity(&mut self, capacity: usize) {
+        self.decision_log_capacity = capacity;
+        if self.decision_log.len() > capacity {
+            let excess: usize = self.decision_log.len() - capacity;
+            self.decision_log.drain(..excess);
+        }
+    }
+
+    pub fn decision_log(&self) -> &[LoggedDecision] {
+        &self.decision_log
+    }
+
+    pub fn set_decision_mode(&mut self, mode: DecisionMode) {
+        self.decision_mode = mode;
+    }
+
+    pub fn decision_mode(&self) -> DecisionMode {
+        self.decision_mode
+    }
+
+    pub fn set_qoe_model(&mut self, model: Box<dyn QoeModel>) {
+        self.qoe_model = model;
+    }
+
+    // Most frequent raw target over the window. Ties keep the current quality
+    // if it is among them, otherwise the lowest of the tied qualities wins.
+    fn mode_smoothed_quality(&mut self, target_quality: usize, window: usize) -> usize {
+        self.raw_target_history.push_back(target_quality);
+        while self.raw_target_history.len() > window.max(1) {
+            self.raw_target_history.pop_front();
+        }
+        
+        let mut counts: Vec<usize> = vec![0; self.quality_levels.len()];
+        for &quality in &self.raw_target_history {
+            counts[quality] += 1;
+        }
+        
+        let max_count: usize = counts.iter().copied().max().unwrap_or(0);
+        if counts[self.current_quality] == max_count {
+            return self.current_quality;
+        }
+        counts.iter().position(|&count| count == max_count).unwrap_or(self.current_quality)
+    }
+
+    // Exhaustive search over every quality sequence of `horizon` segments,
+    // simulating the buffer with the current estimate. Returns the first
+    // quality of the best-scoring plan.
+    fn plan_mpc_quality(&self, horizon: usize) -> usize {
+        let horizon: usize = horizon.clamp(1, MAX_MPC_HORIZON);
+        let levels: usize = self.quality_levels.len();
+        let download_times: Vec<Duration> = (0..levels).map(|i| self.predicted_download_time(i)).collect();
+        let segment_duration: Duration = self.average_segment_duration().unwrap_or(DEFAULT_SEGMENT_DURATION);
+        
+        let mut best_quality: usize = self.current_quality;
+        let mut best_score: f64 = f64::NEG_INFINITY;
+        let mut plan: Vec<usize> = vec![0; horizon];
+        
+        // The first segment may go to any level; later ones move at most one
+        // rung from the one before, so the search is levels * 3^(horizon - 1)
+        // rather than levels^horizon
+        let step_plans: usize = 3usize.pow(horizon as u32 - 1);
+        for first_quality in 0..levels {
+            'plans: for steps in 0..step_plans {
+                plan[0] = first_quality;
+                let mut remaining: usize = steps;
+                for slot in 1..horizon {
+                    let quality: usize = match remaining % 3 {
+                        0 => plan[slot - 1],
+                        1 => plan[slot - 1] + 1,
+                        _ => plan[slot - 1].wrapping_sub(1),
+                    };
+                    if quality >= levels {
+                        continue 'plans;
+                    }
+                    plan[slot] = quality;
+                    remaining /= 3;
+                }
+                
+                let decodable: bool = plan.iter().all(|&quality| self.is_quality_supported(quality));
+                let session: SessionStats = self.project_plan(&plan, &download_times, segment_duration);
+                let score: f64 = self.qoe_model.score(&session);
+                if decodable && score > best_score {
+                    best_score = score;
+                    best_quality = plan[0];
+                }
+            }
+        }
+        
+        best_quality
+    }
+
+    fn project_plan(&self, plan: &[usize], download_times: &[Duration], segment_duration: Duration) -> SessionStats {
+        let mut buffer: Duration = self.buffer_state.current_level;
+        let mut previous_quality: usize = self.current_quality;
+        let mut session: SessionStats = SessionStats {
+            bitrates: Vec::with_capacity(plan.len()),
+            utilities: Vec::with_capacity(plan.len()),
+            segment_durations: vec![segment_duration; plan.len()],
+            rebuffer_time: Duration::from_secs(0),
+            switches: 0,
+            switch_magnitude: 0.0,
+        };
+        
+        for &quality in plan {
+            let download_time: Duration = download_times[quality];
+            if download_time > buffer {
+                session.rebuffer_time += download_time - buffer;
+            }
+            buffer = (buffer.saturating_sub(download_time) + segment_duration).min(self.buffer_state.max_level);
+            
+            if quality != previous_quality {
+                session.switches += 1;
+                session.switch_magnitude += (self.quality_utility(quality) - self.quality_utility(previous_quality)).abs();
+            }
+            session.bitrates.push(self.quality_levels[quality].bitrate);
+            session.utilities.push(self.quality_utility(quality));
+            previous_quality = quality;
+        }
+        
+        session
+    }
+
+    // Stall accumulated over the next `horizon_segments` if `quality_index` were
+    // held throughout, from the current estimate and buffer. Duration::MAX for
+    // an index outside the ladder.
+    pub fn expected_rebuffer_for(&self, quality_index: usize, horizon_segments: usize) -> Duration {
+        if quality_index >= self.quality_levels.len() {
+            return Duration::MAX;
+        }
+        
+        let download_times: Vec<Duration> = (0..self.quality_levels.len())
+            .map(|i| self.predicted_download_time(i))
+            .collect();
+        let segment_duration: Duration = self.average_segment_duration().unwrap_or(DEFAULT_SEGMENT_DURATION);
+        let plan: Vec<usize> = vec![quality_index; horizon_segments];
+        self.project_plan(&plan, &download_times, segment_duration).rebuffer_time
+    }
+
+    // Active QoE model's score for fetching `quality_index` next: its utility,
+    // the stall it risks, and the switch cost from the current quality. The
+    // stall is however long the download runs past `time_to_rebuffer` at that
+    // quality. One step of the MPC objective.
+    pub fn expected_qoe_of(&self, quality_index: usize) -> f64 {
+        if quality_index >= self.quality_levels.len() {
+            return f64::NEG_INFINITY;
+        }
+        
+        let download_times: Vec<Duration> = (0..self.quality_levels.len())
+            .map(|i| self.predicted_download_time(i))
+            .collect();
+        let segment_duration: Duration = self.average_segment_duration().unwrap_or(DEFAULT_SEGMENT_DURATION);
+        let mut session: SessionStats = self.project_plan(&[quality_index], &download_times, segment_duration);
+        session.rebuffer_time = match self.time_to_rebuffer_at(quality_index) {
+            Some(time_to_rebuffer) => download_times[quality_index].saturating_sub(time_to_rebuffer),
+            None => Duration::from_secs(0),
+        };
+        let score: f64 = self.qoe_model.score(&session);
+        if score.is_nan() { f64::NEG_INFINITY } else { score }
+    }
+
+    // Gates a proposed up-switch has to pass; a blocked up-switch holds the current quality
+    fn apply_upswitch_gates(
+        &self,
+        current_quality: usize,
+        proposed_quality: usize,
+        effective_bandwidth: u32,
+    ) -> (usize, Option<SuppressionReason>) {
+        // Settle for the highest intermediate level whose headroom is met
+        let allowed_quality: usize = match (current_quality + 1..=proposed_quality).rev().find(|&i| {
+            bitrate_to_byte_rate(self.quality_levels[i].bitrate) as f64 * self.upswitch_margin_for(i) <= effective_bandwidth as f64
+        }) {
+            Some(quality) => quality,
+            None => return (current_quality, Some(SuppressionReason::Margin)),
+        };
+        
+        let margin_quality: usize = allowed_quality;
+        let allowed_quality: usize = match (current_quality + 1..=margin_quality)
+            .rev()
+            .find(|&i| self.sustained_by_recent_samples(i))
+        {
+            Some(quality) => quality,
+            None => return (current_quality, Some(SuppressionReason::Transient)),
+        };
+        
+        if self.upswitch_buffer_safety
+            && self.predicted_download_time(allowed_quality) >= self.buffer_state.current_level
+        {
+            return (current_quality, Some(SuppressionReason::BufferSafety));
+        }
+        
+        let reason: Option<SuppressionReason> = if allowed_quality < margin_quality {
+            Some(SuppressionReason::Transient)
+        } else {
+            (allowed_quality < proposed_quality).then_some(SuppressionReason::Margin)
+        };
+        (allowed_quality, reason)
+    }
+
+    // In panic, keep the effective bandwidth at or above the lowest rendition's
+    // rate so the budget never collapses towards zero
+    pub fn set_panic_bandwidth_floor(&mut self, enabled: bool) {
+        self.panic_bandwidth_floor = enabled;
+    }
+
+    // Require an up-switch to be backed by each of the last `samples` raw
+    // measurements, so a single spike can't trigger one. 0 disables the check.
+    pub fn set_min_upswitch_samples(&mut self, samples: usize) {
+        self.min_upswitch_samples = samples;
+    }
+
+    fn sustained_by_recent_samples(&self, quality_index: usize) -> bool {
+        if self.min_upswitch_samples == 0 {
+            return true;
+        }
+        if self.bandwidth_history.len() < self.min_upswitch_samples {
+            return false;
+        }
+        
+        let required: f64 = bitrate_to_byte_rate(self.quality_levels[quality_index].bitrate) as f64;
+        let utilization: f64 = self.effective_utilization();
+        self.bandwidth_history
+            .iter()
+            .rev()
+            .take(self.min_upswitch_samples)
+            .all(|(_, bandwidth)| *bandwidth as f64 * utilization >= required)
+    }
+
+    // Hybrid's throughput side: the estimate under the safety factor alone
+    fn throughput_target_quality(&self, estimated_bandwidth: u32) -> usize {
+        let mut budget: f64 = estimated_bandwidth as f64;
+        if self.utilization_layers.safety_factor {
+            budget *= self.safety_factor as f64 * self.startup_safety();
+        }
+        self.find_suitable_quality(budget as u32)
+    }
+
+    // Hybrid's buffer side: the ladder mapped linearly from min_level (lowest)
+    // to target_level (highest), ignoring throughput
+    fn buffer_target_quality(&self) -> usize {
+        let top: usize = self.quality_levels.len() - 1;
+        let min_level: f64 = self.buffer_state.min_level.as_secs_f64();
+        let span: f64 = self.buffer_state.target_level.as_secs_f64() - min_level;
+        if span <= 0.0 {
+            return if self.buffer_state.current_level >= self.buffer_state.target_level { top } else { 0 };
+        }
+        
+        let fraction: f64 = ((self.buffer_state.current_level.as_secs_f64() - min_level) / span).clamp(0.0, 1.0);
+        (fraction * top as f64).round() as usize
+    }
+
+    // With probability `epsilon`, and only on a healthy buffer, try one level
+    // above the regular decision to learn whether the link sustains it
+    fn explore_quality(&mut self, quality: usize, epsilon: f64) -> usize {
+        if epsilon <= 0.0 || quality + 1 >= self.quality_levels.len() || !self.is_buffer_healthy() {
+            return quality;
+        }
+        if self.rng.next_f64() >= epsilon {
+            return quality;
+        }
+        
+        self.pending_probe = true;
+        self.exploration_probes += 1;
+        quality + 1
+    }
+
+    // (probes attempted, probes whose segment downloaded faster than real time)
+    pub fn exploration_stats(&self) -> (u32, u32) {
+        (self.exploration_probes, self.exploration_successes)
+    }
+
+    fn commit_quality(&mut self, next_quality: usize) {
+        let now: Instant = self.clock.now();
+        
+        if next_quality > self.current_quality {
+            self.up_switches += 1;
+        } else if next_quality < self.current_quality {
+            self.down_switches += 1;
+            
+            // Only a quality that was actually sustained is worth recovering to
+            let sustained: bool = self.quality_entered_at
+                .is_some_and(|entered_at| now.duration_since(entered_at) >= self.stable_quality_duration);
+            if sustained {
+                self.last_stable_quality = Some(self.current_quality);
+            }
+        }
+        
+        if next_quality != self.current_quality {
+            self.last_switch_at = Some(now);
+        }
+        if next_quality != self.current_quality || self.quality_entered_at.is_none() {
+            self.quality_entered_at = Some(now);
+        }
+        if self.last_stable_quality.is_some_and(|stable| next_quality >= stable) {
+            self.last_stable_quality = None;
+        }
+        self.current_quality = next_quality;
+    }
+
+    // How long a quality must be held before a dip below it is remembered
+    pub fn set_stable_quality_duration(&mut self, duration: Duration) {
+        self.stable_quality_duration = duration;
+    }
+
+    pub fn last_stable_quality(&self) -> Option<usize> {
+        self.last_stable_quality
+    }
+
+    // Wall-clock time since the quality index last changed, or since the
+    // session started when it never has
+    pub fn time_since_last_switch(&self) -> Duration {
+        let since: Instant = self.last_switch_at.unwrap_or(self.session_start);
+        self.clock.now().saturating_duration_since(since)
+    }
+
+    // Fraction of the estimated bandwidth that selection is allowed to spend.
+    // The estimate itself is already conservative (min of three estimators),
+    // and on top of that the buffer factor and the safety factor both scale it,
+    // so every layer can be switched off through `UtilizationLayers`.
+    pub fn effective_utilization(&self) -> f64 {
+        let mut utilization: f64 = 1.0;
+        
+        if self.utilization_layers.buffer_factor {
+            utilization *= self.calculate_buffer_factor();
+        }
+        if self.utilization_layers.safety_factor {
+            utilization *= self.safety_factor as f64 * self.startup_safety();
+        }
+        
+        finite_or(utilization, self.panic_factor)
+    }
+
+    // Applied on top of the safety factor with few samples; 1.0 disables it
+    pub fn set_startup_safety_factor(&mut self, factor: f64) -> Result<(), ConfigError> {
+        if !(factor > 0.0 && factor <= 1.0) {
+            return Err(ConfigError::OutOfRange { parameter: "startup_safety_factor", value: factor });
+        }
+        self.startup_safety_factor = factor;
+        Ok(())
+    }
+
+    // Relaxes linearly from the startup factor to 1.0 as samples accumulate
+    fn startup_safety(&self) -> f64 {
+        let required_samples: usize = self.required_samples().max(1);
+        let confidence: f64 = (self.estimation_samples().len() as f64 / required_samples as f64).min(1.0);
+        self.startup_safety_factor + (1.0 - self.startup_safety_factor) * confidence
+    }
+
+    // Budget in bytes per second that selection compares renditions against
+    pub fn effective_bandwidth(&self) -> u32 {
+        (self.estimate_bandwidth() as f64 * self.effective_utilization()) as u32
+    }
+
+    fn estimate_bandwidth(&self) -> u32 {
+        let raw_estimate: u32 = self.raw_estimate_bandwidth();
+        let estimate: u32 = self.finalize_estimate(raw_estimate);
+        abr_trace!("bandwidth estimate {estimate} B/s from raw {raw_estimate} B/s");
+        estimate
+    }
+
+    // Post-rebuffer discount, floor and ceiling, common to every estimate
+    fn finalize_estimate(&self, raw_estimate: u32) -> u32 {
+        let discounted: f64 = raw_estimate as f64 * self.rebuffer_discount();
+        
+        // Keep downstream divisions well-defined under severe congestion
+        let floored: u32 = (discounted as u32).max(self.min_bandwidth_floor);
+        self.bandwidth_ceiling.map_or(floored, |ceiling| floored.min(ceiling))
+    }
+
+    // `factor` scales the estimate right after a rebuffer and ramps back to 1.0
+    // linearly over the next `decay_segments` downloads
+    pub fn set_post_rebuffer_discount(&mut self, factor: f64, decay_segments: u32) -> Result<(), ConfigError> {
+        if !(factor > 0.0 && factor <= 1.0) {
+            return Err(ConfigError::OutOfRange { parameter: "post_rebuffer_discount", value: factor });
+        }
+        self.post_rebuffer_discount = factor;
+        self.post_rebuffer_decay_segments = decay_segments;
+        Ok(())
+    }
+
+    fn rebuffer_discount(&self) -> f64 {
+        let Some(segments) = self.segments_since_rebuffer else {
+            return 1.0;
+        };
+        if segments >= self.post_rebuffer_decay_segments {
+            return 1.0;
+        }
+        
+        let recovered: f64 = segments as f64 / self.post_rebuffer_decay_segments as f64;
+        self.post_rebuffer_discount + (1.0 - self.post_rebuffer_discount) * recovered
+    }
+
+    fn raw_estimate_bandwidth(&self) -> u32 {
+        if let Some(initial_estimate) = self.initial_bandwidth_estimate
+            && self.estimation_samples().is_empty()
+        {
+            return initial_estimate;
+        }
+        
+        if !self.has_confident_estimate() {
+            return match self.network_class.initial_bandwidth_estimate() {
+                Some(bandwidth) => bandwidth,
+                None => bitrate_to_byte_rate(self.quality_levels[self.current_quality].bitrate),
+            };
+        }
+
+        let samples: Vec<(Instant, u32)> = self.estimation_samples();
+        let weighted_average: u32 = self.calculate_weighted_average_bandwidth(&samples);
+        if !self.utilization_layers.min_of_estimators || self.in_steady_state() {
+            return weighted_average;
+        }
+        
+        let harmonic_mean: u32 = self.calculate_harmonic_mean_bandwidth(&samples);
+        let percentile_estimate: u32 = self.calculate_percentile_bandwidth(&samples, 0.2); // 20th percentile for conservative estimate
+        
+        harmonic_mean.min(weighted_average).min(percentile_estimate)
+    }
+
+    // A link that has held a stable coefficient of variation for several
+    // samples is estimated by the weighted average alone, since min-of-three
+    // would leave bandwidth unused
+    pub fn set_steady_state_estimator(&mut self, enabled: bool) {
+        self.steady_state_estimator = enabled;
+    }
+
+    pub fn in_steady_state(&self) -> bool {
+        self.steady_state_estimator && self.steady_samples >= STEADY_STATE_SAMPLES
+    }
+
+    // (max - min) / max across the three estimators: 0.0 when they agree,
+    // approaching 1.0 when an outlier or volatility pulls them apart
+    pub fn estimator_spread(&self) -> f64 {
+        let samples: Vec<(Instant, u32)> = self.estimation_samples();
+        if samples.is_empty() {
+            return 0.0;
+        }
+        
+        let estimates: [u32; 3] = [
+            self.calculate_harmonic_mean_bandwidth(&samples),
+            self.calculate_weighted_average_bandwidth(&samples),
+            self.calculate_percentile_bandwidth(&samples, 0.2),
+        ];
+        let highest: u32 = estimates.iter().copied().max().unwrap_or(0);
+        let lowest: u32 = estimates.iter().copied().min().unwrap_or(0);
+        if highest == 0 {
+            return 0.0;
+        }
+        finite_or((highest - lowest) as f64 / highest as f64, 0.0)
+    }
+
+    // Stable links earn confidence with fewer samples, jittery ones need more
+    pub fn required_samples(&self) -> usize {
+        let cv: f64 = self.bandwidth_cv();
+        
+        if cv < STABLE_BANDWIDTH_CV && self.min_bandwidth_samples > 2 {
+            self.min_bandwidth_samples - 1
+        } else if cv > VOLATILE_BANDWIDTH_CV {
+            self.min_bandwidth_samples * 2
+        } else {
+            self.min_bandwidth_samples
+        }
+    }
+
+    fn has_confident_estimate(&self) -> bool {
+        self.estimation_samples().len() >= self.required_samples()
+    }
+
+    pub fn set_time_bucketing(&mut self, bucket: Option<Duration>) {
+        self.time_bucket = bucket.filter(|bucket| !bucket.is_zero());
+    }
+
+    // The samples estimation works from: the windowed history, or with time
+    // bucketing one mean sample per bucket so bursts don't dominate
+    fn estimation_samples(&self) -> Vec<(Instant, u32)> {
+        let mut history: Vec<(Instant, u32)> = self.bandwidth_history.iter().copied().collect();
+        if let Some(first_sample) = self.excluded_first_sample()
+            && let Some(position) = history.iter().position(|sample| *sample == first_sample)
+        {
+            history.remove(position);
+        }
+        
+        let Some(bucket) = self.time_bucket else {
+            return history;
+        };
+        
+        // Buckets are counted back from now, oldest first like the history
+        let now: Instant = self.clock.now();
+        let mut buckets: Vec<(u64, Instant, u64, u64)> = Vec::new(); // (index, latest, sum, count)
+        for &(timestamp, bandwidth) in &history {
+            let index: u64 = (now.duration_since(timestamp).as_secs_f64() / bucket.as_secs_f64()) as u64;
+            match buckets.last_mut() {
+                Some(last) if last.0 == index => {
+                    last.1 = timestamp;
+                    last.2 += bandwidth as u64;
+                    last.3 += 1;
+                }
+                _ => buckets.push((index, timestamp, bandwidth as u64, 1)),
+            }
+        }
+        
+        buckets
+            .into_iter()
+            .map(|(_, timestamp, sum, count)| (timestamp, (sum / count) as u32))
+            .collect()
+    }
+
+    // Coefficient of variation (stddev / mean) over the estimation samples,
+    // the shared stability signal for adaptive tuning
+    pub fn bandwidth_cv(&self) -> f64 {
+        let samples: Vec<(Instant, u32)> = self.estimation_samples();
+        if samples.len() < 2 {
+            return 0.0;
+        }
+        
+        let count: f64 = samples.len() as f64;
+        let mean: f64 = samples.iter().map(|(_, bw)| *bw as f64).sum::<f64>() / count;
+        if mean <= 0.0 {
+            return 0.0;
+        }
+        
+        let variance: f64 = samples
+            .iter()
+            .map(|(_, bw)| (*bw as f64 - mean).powi(2))
+            .sum::<f64>() / count;
+        finite_or(variance.sqrt() / mean, 0.0)
+    }
+
+    fn calculate_harmonic_mean_bandwidth(&self, samples: &[(Instant, u32)]) -> u32 {
+        if samples.is_empty() {
+            return 0;
+        }
+        
+        let sum_reciprocals: f64 = samples
+            .iter()
+            .map(|(_, bw)| 1.0 / (*bw as f64).max(1.0))
+            .sum();
+        
+        (samples.len() as f64 / sum_reciprocals) as u32
+    }
+
+    fn calculate_weighted_average_bandwidth(&self, samples: &[(Instant, u32)]) -> u32 {
+        let now: Instant = self.clock.now();
+        let mut weighted_sum: f64 = 0.0;
+        let mut weight_sum: f64 = 0.0;
+        let size_weights: Option<Vec<f64>> = self.size_weighted_estimate.then(|| self.sample_size_weights(samples));
+        
+        for (i, (timestamp, bandwidth)) in samples.iter().enumerate() {
+            let age = now.duration_since(*timestamp).as_secs_f64();
+            let mut weight = finite_or(self.decay_weight(age), 1.0);
+            if let Some(size_weights) = &size_weights {
+                weight *= size_weights[i];
+            }
+            
+            weighted_sum += *bandwidth as f64 * weight;
+            weight_sum += weight;
+        }
+        
+        if weight_sum > 0.0 {
+            (weighted_sum / weight_sum) as u32
+        } else {
+            0
+        }
+    }
+
+    pub fn set_decay_kernel(&mut self, kernel: DecayKernel) {
+        self.decay_kernel = kernel;
+    }
+
+    fn decay_weight(&self, age_secs: f64) -> f64 {
+        let relative_age: f64 = age_secs / self.bandwidth_window.as_secs_f64();
+        match self.decay_kernel {
+            DecayKernel::Exponential => (-relative_age).exp(),
+            DecayKernel::Linear => (1.0 - relative_age).max(0.0),
+            DecayKernel::None => 1.0,
+        }
+    }
+
+    // Larger transfers measure throughput more reliably, so with size weighting
+    // every sample counts in proportion to its segment's bytes
+    pub fn set_size_weighted_estimate(&mut self, enabled: bool) {
+        self.size_weighted_estimate = enabled;
+    }
+
+    // Pairs each sample with the segment recorded at the same instant; both
+    // histories are oldest first. Unpaired samples (seeded, or whose segment
+    // left the history) weigh as an average segment.
+    fn sample_size_weights(&self, samples: &[(Instant, u32)]) -> Vec<f64> {
+        let average_size: f64 = if self.segment_history.is_empty() {
+            1.0
+        } else {
+            self.segment_history.iter().map(|segment| segment.size_bytes as f64).sum::<f64>()
+                / self.segment_history.len() as f64
+        };
+        
+        let mut segments = self.segment_history.iter().peekable();
+        samples
+            .iter()
+            .map(|(timestamp, _)| {
+                while segments.next_if(|segment| segment.recorded_at < *timestamp).is_some() {}
+                match segments.next_if(|segment| segment.recorded_at == *timestamp) {
+                    Some(segment) => segment.size_bytes as f64,
+                    None => average_size,
+                }
+            })
+            .collect()
+    }
+
+    fn calculate_percentile_bandwidth(&self, samples: &[(Instant, u32)], percentile: f64) -> u32 {
+        let mut bandwidths: Vec<u32> = samples
+            .iter()
+            .map(|(_, bw)| *bw)
+            .collect();
+        
+        if bandwidths.is_empty() {
+            return 0;
+        }
+        
+        bandwidths.sort_unstable();
+        let index: usize = (bandwidths.len() as f64 * percentile) as usize;
+        bandwidths[index.min(bandwidths.len() - 1)]
+    }
+
+    fn calculate_buffer_factor(&self) -> f64 {
+        self.buffer_factor_at(self.buffer_state.current_level)
+    }
+
+    // The piecewise buffer-factor curve evaluated at an arbitrary level; the
+    // seeking branch still reflects the current seeking-mode decay
+    // Falls back to the panic factor, the most conservative value, if the
+    // thresholds are degenerate enough to make the curve non-finite
+    pub fn buffer_factor_at(&self, level: Duration) -> f64 {
+        finite_or(self.raw_buffer_factor_at(level), self.panic_factor)
+    }
+
+    // Low-latency mode shrinks the target to the latency budget and scales the
+    // panic threshold down with it, so the whole buffer curve moves closer to
+    // the live edge. It also caps selection at renditions that download
+    // faster than real time.
+    pub fn set_latency_mode(&mut self, mode: LatencyMode) {
+        self.latency_mode = mode;
+    }
+
+    pub fn latency_mode(&self) -> LatencyMode {
+        self.latency_mode
+    }
+
+    fn latency_target_level(&self) -> Duration {
+        match self.latency_mode {
+            LatencyMode::Normal => self.buffer_state.target_level,
+            LatencyMode::LowLatency { target_latency } => self.buffer_state.target_level.min(target_latency),
+        }
+    }
+
+    // The target the buffer curve works against, after the latency budget and
+    // the startup warmup
+    fn effective_target_level(&self) -> Duration {
+        let target_level: Duration = self.latency_target_level();
+        if self.target_warmup.is_zero() {
+            return target_level;
+        }
+        
+        let progress: f64 = (self.session_elapsed().as_secs_f64() / self.target_warmup.as_secs_f64()).min(1.0);
+        let start_level: Duration = self.buffer_state.min_level.min(target_level);
+        start_level + (target_level - start_level).mul_f64(progress)
+    }
+
+    // Only the latency budget moves the panic threshold; the warmup does not
+    fn effective_panic_threshold(&self) -> Duration {
+        let target_level: Duration = self.buffer_state.target_level;
+        if target_level.is_zero() {
+            return self.buffer_panic_threshold;
+        }
+        self.buffer_panic_threshold.mul_f64(self.latency_target_level().as_secs_f64() / target_level.as_secs_f64())
+    }
+
+    // Ramp the target from min_level up to target_level over the first
+    // `warmup` of the session, so startup isn't held to the full target.
+    // Zero disables the ramp.
+    pub fn set_target_warmup(&mut self, warmup: Duration) {
+        self.target_warmup = warmup;
+    }
+
+    fn live_edge_quality_cap(&self) -> Option<usize> {
+        let LatencyMode::LowLatency { .. } = self.latency_mode else {
+            return None;
+        };
+        let segment_duration: Duration = self.average_segment_duration().unwrap_or(DEFAULT_SEGMENT_DURATION);
+        let real_time_quality: usize = (0..self.quality_levels.len())
+            .rev()
+            .find(|&i| self.predicted_download_time(i) <= segment_duration)
+            .unwrap_or(0);
+        Some(real_time_quality)
+    }
+
+    // Each threshold belongs to the band above it: exactly panic_threshold is
+    // already on the ramp, exactly target_level is normal, and exactly
+    // seek_threshold is still normal. The ramp meets 1.0 at the target.
+    fn raw_buffer_factor_at(&self, level: Duration) -> f64 {
+        let current_buffer: f64 = level.as_secs_f64();
+        let target_buffer: f64 = self.effective_target_level().as_secs_f64();
+        let panic_threshold: f64 = self.effective_panic_threshold().as_secs_f64();
+        let seek_threshold: f64 = self.seek_threshold().as_secs_f64();
+        
+        if current_buffer < panic_threshold {
+            // Buffer panic: be very conservative
+            self.panic_factor
+        } else if current_buffer < target_buffer {
+            // Below target: be somewhat conservative
+            0.6 + 0.4 * (current_buffer / target_buffer)
+        } else if current_buffer > seek_threshold {
+            // Buffer seeking: can be more aggressive
+            self.seeking_buffer_factor()
+        } else {
+            // Normal operation
+            1.0
+        }
+    }
+
+    // `available_bandwidth` is expected to already include the safety factor.
+    // Falls back to 0 when nothing fits; see `find_feasible_quality`.
+    fn find_suitable_quality(&self, available_bandwidth: u32) -> usize {
+        self.find_feasible_quality(available_bandwidth)
+            .unwrap_or_else(|| self.snap_to_supported_quality(0))
+    }
+
+    pub fn set_supported_codecs(&mut self, codecs: HashSet<String>) {
+        self.supported_codecs = Some(codecs);
+    }
+
+    // Resolution beyond the viewport is never displayed, so selection stops at
+    // the smallest rendition that covers it
+    pub fn set_viewport(&mut self, width: u32, height: u32) {
+        self.viewport = Some((width, height));
+    }
+
+    pub fn clear_viewport(&mut self) {
+        self.viewport = None;
+    }
+
+    // None without a viewport or when no rendition covers it
+    fn viewport_quality_cap(&self) -> Option<usize> {
+        let (width, height) = self.viewport?;
+        self.quality_levels
+            .iter()
+            .position(|quality| quality.width >= width && quality.height >= height)
+    }
+
+    fn is_quality_supported(&self, index: usize) -> bool {
+        match &self.supported_codecs {
+            Some(codecs) => codecs.contains(&self.quality_levels[index].codec),
+            None => true,
+        }
+    }
+
+    // Highest decodable quality at or below `quality`, else the lowest
+    // decodable one above it. Unchanged when nothing is decodable.
+    fn snap_to_supported_quality(&self, quality: usize) -> usize {
+        (0..=quality)
+            .rev()
+            .chain(quality + 1..self.quality_levels.len())
+            .find(|&i| self.is_quality_supported(i))
+            .unwrap_or(quality)
+    }
+
+    // Among the feasible levels, the preference decides: highest bitrate (ties
+    // to the fewer pixels, e.g. a high-fps rendition) or highest resolution
+    fn find_feasible_quality(&self, available_bandwidth: u32) -> Option<usize> {
+        let feasible = (0..self.quality_levels.len()).filter(|&i| self.is_quality_feasible(i, available_bandwidth));
+        let pixels = |i: usize| -> u64 { self.quality_levels[i].width as u64 * self.quality_levels[i].height as u64 };
+        
+        match self.selection_preference {
+            SelectionPreference::Bitrate => {
+                feasible.max_by_key(|&i| (self.quality_levels[i].bitrate, Reverse(pixels(i)), i))
+            }
+            SelectionPreference::Resolution => {
+                feasible.max_by_key(|&i| (pixels(i), self.quality_levels[i].bitrate, i))
+            }
+        }
+    }
+
+    fn is_quality_feasible(&self, i: usize, available_bandwidth: u32) -> bool {
+        if !self.is_quality_supported(i) {
+            return false;
+        }
+        
+        if self.viewport_quality_cap().is_some_and(|cap| i > cap) {
+            return false;
+        }
+        
+        // Only explicit entries gate selection, at any current quality; a
+        // buffer under the global min_level is left to the panic branch
+        let explicit_min_buffer: Option<Duration> = self.min_buffer_per_quality.get(i).copied().flatten();
+        if explicit_min_buffer.is_some_and(|min_buffer| self.buffer_state.current_level < min_buffer) {
+            return false;
+        }
+        
+        // Dead-band: entering a level costs more than staying in it
+        let mut required_bandwidth: f64 = bitrate_to_byte_rate(self.quality_levels[i].bitrate) as f64;
+        if i > self.current_quality {
+            required_bandwidth *= 1.0 + self.selection_hysteresis;
+        }
+        required_bandwidth <= self.bandwidth_budget(available_bandwidth)
+    }
+
+    pub fn set_selection_preference(&mut self, preference: SelectionPreference) {
+        self.selection_preference = preference;
+    }
+
+    fn bandwidth_budget(&self, available_bandwidth: u32) -> f64 {
+        match self.boundary_policy {
+            BoundaryPolicy::StrictFit => available_bandwidth as f64,
+            BoundaryPolicy::NearestWithinTolerance(tolerance) => available_bandwidth as f64 * (1.0 + tolerance),
+        }
+    }
+
+    pub fn set_boundary_policy(&mut self, policy: BoundaryPolicy) -> Result<(), ConfigError> {
+        if let BoundaryPolicy::NearestWithinTolerance(tolerance) = policy
+            && !(0.0..1.0).contains(&tolerance)
+        {
+            return Err(ConfigError::OutOfRange { parameter: "boundary_tolerance", value: tolerance });
+        }
+        self.boundary_policy = policy;
+        Ok(())
+    }
+
+    pub fn set_max_upswitch_steps_when_full(&mut self, steps: usize) {
+        self.max_upswitch_steps_when_full = steps.max(1);
+    }
+
+    pub fn set_selection_hysteresis(&mut self, hysteresis: f64) -> Result<(), ConfigError> {
+        if !(0.0..1.0).contains(&hysteresis) {
+            return Err(ConfigError::OutOfRange { parameter: "selection_hysteresis", value: hysteresis });
+        }
+        self.selection_hysteresis = hysteresis;
+        Ok(())
+    }
+
+    // With the buffer at or past its target there is room to fetch ahead of
+    // what is displayed, so this skips the step smoothing applied to the
+    // committed quality. Otherwise it stays with the current quality.
+    pub fn recommended_fetch_quality(&self) -> usize {
+        if self.buffer_state.current_level < self.buffer_state.target_level {
+            return self.current_quality;
+        }
+        self.find_suitable_quality(self.effective_bandwidth())
+    }
+
+    // False when even the lowest rendition exceeds the budget, in which case
+    // the player may want to pause and build buffer, or when no rendition
+    // uses a supported codec
+    pub fn has_feasible_quality(&self) -> bool {
+        self.find_feasible_quality(self.effective_bandwidth()).is_some()
+    }
+
+    fn apply_quality_smoothing(&self, target_quality: usize) -> usize {
+        let current = self.current_quality as i32;
+        let target = target_quality as i32;
+        let diff = target - current;
+        
+        // Limit quality changes to prevent oscillations
+        let max_change = if self.buffer_state.current_level < self.effective_panic_threshold() {
+            // In panic mode, allow immediate downgrade
+            if diff < 0 { self.panic_landing_quality(target_quality) as i32 - current } else { 1 }
+        } else if let Some(stable) = self.last_stable_quality.filter(|&stable| diff > 0 && stable > self.current_quality) {
+            // Recovering from a dip: climb straight back toward the last
+            // sustained quality; the up-switch gates still apply afterwards
+            target.min(stable as i32) - current
+        } else if diff > 0 && self.buffer_state.current_level > self.seek_threshold() {
+            // A buffer this deep can absorb a bigger jump
+            diff.min(self.max_upswitch_steps_when_full as i32)
+        } else {
+            // Normal operation: limit changes
+            diff.signum() * 1.min(diff.abs())
+        };
+        
+        ((current + max_change).max(0) as usize).min(self.quality_levels.len() - 1)
+    }
+
+    // Soft landing: a panic downgrade stops at the highest quality whose
+    // downloads still keep up with playback instead of falling to the panic
+    // target. A nearly empty buffer skips this and takes the target directly.
+    fn panic_landing_quality(&self, target_quality: usize) -> usize {
+        if self.should_pause_playback() {
+            return target_quality;
+        }
+        
+        let segment_duration: Duration = self.average_segment_duration().unwrap_or(DEFAULT_SEGMENT_DURATION);
+        let sustainable_quality: usize = (0..=self.current_quality)
+            .rev()
+            .find(|&i| self.predicted_download_time(i) <= segment_duration)
+            .unwrap_or(0);
+        
+        target_quality.max(sustainable_quality).min(self.current_quality)
+    }
+
+    fn cleanup_bandwidth_history(&mut self, now: Instant) {
+        while let Some((timestamp, _)) = self.bandwidth_history.front() {
+            if now.duration_since(*timestamp) > self.bandwidth_window {
+                self.bandwidth_history.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    // Byte size of the next segment at the current quality, typically read from
+    // the manifest. Other qualities scale it by bitrate. Cleared by the next decision.
+    pub fn set_next_segment_size_hint(&mut self, size_bytes: Option<u32>) {
+        self.next_segment_size_hint = size_bytes.map(|size_bytes| (self.current_quality, size_bytes));
+    }
+
+    pub fn predicted_download_time(&self, quality_index: usize) -> Duration {
+        let estimated_bandwidth: u32 = self.estimate_bandwidth();
+        let segment_size: f64 = match self.next_segment_size_hint {
+            Some((hinted_quality, size_bytes)) => {
+                let bitrate_ratio: f64 = self.quality_levels[quality_index].bitrate as f64
+                    / self.quality_levels[hinted_quality].bitrate.max(1) as f64;
+                size_bytes as f64 * bitrate_ratio
+            }
+            None => self.estimated_segment_size(quality_index) as f64,
+        };
+        Duration::from_secs_f64(segment_size / estimated_bandwidth as f64)
+    }
+
+    // Prefers the observed average size at this quality, since bitrate x duration
+    // overestimates VBR content
+    // Scaled by `complexity_factor` when segments carry complexity hints.
+    pub fn estimated_segment_size(&self, quality_index: usize) -> u32 {
+        (self.typical_segment_size(quality_index) as f64 * self.complexity_factor()) as u32
+    }
+
+    fn typical_segment_size(&self, quality_index: usize) -> u32 {
+        let bitrate: u32 = self.quality_levels[quality_index].bitrate;
+        
+        if self.empirical_segment_sizes {
+            let (total_bytes, count) = self.segment_history
+                .iter()
+                .filter(|segment| segment.bitrate == bitrate)
+                .fold((0u64, 0u64), |(bytes, count), segment| (bytes + segment.size_bytes as u64, count + 1));
+            if let Some(average) = total_bytes.checked_div(count) {
+                return average as u32;
+            }
+        }
+        
+        let segment_duration: Duration = self.average_segment_duration().unwrap_or(DEFAULT_SEGMENT_DURATION);
+        (bitrate_to_byte_rate(bitrate) as f64 * segment_duration.as_secs_f64()) as u32
+    }
+
+    // Projected time until the buffer runs dry if the current quality keeps
+    // being fetched back to back. None when downloads keep up with playback.
+    pub fn time_to_rebuffer(&self) -> Option<Duration> {
+        self.time_to_rebuffer_at(self.current_quality)
+    }
+
+    fn time_to_rebuffer_at(&self, quality_index: usize) -> Option<Duration> {
+        let segment_duration: f64 = self.average_segment_duration().unwrap_or(DEFAULT_SEGMENT_DURATION).as_secs_f64();
+        let download_time: f64 = self.predicted_download_time(quality_index).as_secs_f64();
+        if download_time <= segment_duration {
+            return None;
+        }
+        
+        // Buffer-seconds lost per wall-clock second
+        let drain_rate: f64 = 1.0 - segment_duration / download_time;
+        Some(Duration::from_secs_f64(self.buffer_state.current_level.as_secs_f64() / drain_rate))
+    }
+
+    // Advisory only: the highest lower quality whose downloads keep up with
+    // playback, when the current one is projected to stall
+    pub fn suggested_preemptive_quality(&self) -> Option<usize> {
+        self.time_to_rebuffer()?;
+        
+        let segment_duration: Duration = self.average_segment_duration().unwrap_or(DEFAULT_SEGMENT_DURATION);
+        Some(
+            (0..self.current_quality)
+                .rev()
+                .find(|&i| self.predicted_download_time(i) <= segment_duration)
+                .unwrap_or(0),
+        )
+    }
+
+    fn highest_quality_within_deadline(&self) -> Option<usize> {
+        let deadline: Instant = self.next_segment_deadline?;
+        let available: Duration = deadline
+            .saturating_duration_since(self.clock.now())
+            .saturating_sub(self.deadline_margin);
+        
+        Some(
+            (0..self.quality_levels.len())
+                .rev()
+                .find(|&i| self.predicted_download_time(i) <= available)
+                .unwrap_or(0),
+        )
+    }
+
+    pub fn get_current_quality(&self) -> &QualityLevel {
+        &self.quality_levels[self.current_quality]
+    }
+
+    pub fn quality_level(&self, index: usize) -> Option<&QualityLevel> {
+        self.quality_levels.get(index)
+    }
+
+    pub fn get_buffer_state(&self) -> &BufferState {
+        &self.buffer_state
+    }
+
+    pub fn content_stats(&self, content_tag: ContentType) -> ContentStats {
+        self.content_stats.get(&content_tag).copied().unwrap_or_default()
+    }
+
+    // Average duration of main-content segments; ad breaks are excluded
+    pub fn average_segment_duration(&self) -> Option<Duration> {
+        self.content_stats(ContentType::Main).average_segment_duration()
+    }
+
+    pub fn total_segments(&self) -> usize {
+        self.content_stats.values().map(|stats| stats.segment_count).sum()
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.content_stats.values().map(|stats| stats.total_bytes).sum()
+    }
+
+    pub fn total_transferred_bytes(&self) -> u64 {
+        self.transferred_bytes
+    }
+
+    // Unaffected by the estimation window; 0 before any measurement
+    pub fn peak_bandwidth(&self) -> u32 {
+        self.peak_bandwidth
+    }
+
+    pub fn reset_peak_bandwidth(&mut self) {
+        self.peak_bandwidth = 0;
+    }
+
+    // (seconds ago, bytes per second), oldest first
+    pub fn bandwidth_history_table(&self) -> Vec<(f64, u32)> {
+        let now: Instant = self.clock.now();
+        self.bandwidth_history
+            .iter()
+            .map(|(timestamp, bandwidth)| (now.saturating_duration_since(*timestamp).as_secs_f64(), *bandwidth))
+            .collect()
+    }
+
+    // (seconds ago, quality level, size in bytes, duration secs, download secs), oldest first
+    pub fn segment_history_table(&self) -> Vec<(f64, usize, u32, f64, f64)> {
+        let now: Instant = self.clock.now();
+        self.segment_history
+            .iter()
+            .map(|segment| (
+                now.saturating_duration_since(segment.recorded_at).as_secs_f64(),
+                segment.quality_level,
+                segment.size_bytes,
+                segment.duration.as_secs_f64(),
+                segment.download_time.as_secs_f64(),
+            ))
+            .collect()
+    }
+
+    pub fn rebuffer_count(&self) -> u32 {
+        self.rebuffer_count
+    }
+
+    pub fn rebuffer_time(&self) -> Duration {
+        self.rebuffer_time
+    }
+
+    pub fn up_switches(&self) -> u32 {
+        self.up_switches
+    }
+
+    pub fn down_switches(&self) -> u32 {
+        self.down_switches
+    }
+
+    pub fn quality_distribution(&self) -> &[Duration] {
+        &self.time_at_quality
+    }
+
+    // `quality_distribution` summed per tier; renditions below every tier are left out
+    pub fn quality_tier_distribution(&self, tiers: &[ResolutionTier]) -> HashMap<ResolutionTier, Duration> {
+        let mut distribution: HashMap<ResolutionTier, Duration> = HashMap::new();
+        
+        for (quality, duration) in self.quality_levels.iter().zip(&self.time_at_quality) {
+            let tier: Option<&ResolutionTier> = tiers
+                .iter()
+                .filter(|tier| quality.height >= tier.min_height)
+                .max_by_key(|tier| tier.min_height);
+            if let Some(tier) = tier {
+                *distribution.entry(*tier).or_default() += *duration;
+            }
+        }
+        
+        distribution
+    }
+
+    // Mean absolute bitrate change between consecutive played segments over the
+    // mean bitrate; 0.0 is perfectly steady. 0.0 with fewer than two segments.
+    pub fn bitrate_smoothness(&self) -> f64 {
+        if self.segment_history.len() < 2 {
+            return 0.0;
+        }
+        
+        let bitrates: Vec<f64> = self.segment_history.iter().map(|segment| segment.bitrate as f64).collect();
+        let mean_bitrate: f64 = bitrates.iter().sum::<f64>() / bitrates.len() as f64;
+        let mean_change: f64 = bitrates.windows(2).map(|pair| (pair[1] - pair[0]).abs()).sum::<f64>()
+            / (bitrates.len() - 1) as f64;
+        finite_or(mean_change / mean_bitrate, 0.0)
+    }
+
+    pub fn compute_qoe(&self, weights: QoeWeights) -> f64 {
+        weights.bitrate * self.session_utility
+            - weights.rebuffer * self.rebuffer_time.as_secs_f64()
+            - weights.switch * self.session_switch_utility
+    }
+
+    // Same as `compute_qoe` but only counting events in the trailing window
+    pub fn compute_windowed_qoe(&self, window: Duration, weights: QoeWeights) -> f64 {
+        let now: Instant = self.clock.now();
+        let in_window = |timestamp: &Instant| now.duration_since(*timestamp) <= window;
+        
+        let mut utility: f64 = 0.0;
+        let mut switch_utility: f64 = 0.0;
+        let mut previous: Option<&SegmentInfo> = None;
+        for segment in self.segment_history.iter().filter(|segment| in_window(&segment.recorded_at)) {
+            let segment_utility: f64 = self.quality_utility(segment.quality_level);
+            utility += segment_utility * segment.duration.as_secs_f64();
+            if let Some(previous) = previous {
+                switch_utility += (segment_utility - self.quality_utility(previous.quality_level)).abs();
+            }
+            previous = Some(segment);
+        }
+        
+        let rebuffer_time: f64 = self.rebuffer_events
+            .iter()
+            .filter(|(timestamp, _)| in_window(timestamp))
+            .map(|(_, stall)| stall.as_secs_f64())
+            .sum();
+        
+        weights.bitrate * utility - weights.rebuffer * rebuffer_time - weights.switch * switch_utility
+    }
+
+    // The recorded segments still in history, in the form QoE models score
+    pub fn session_stats(&self) -> SessionStats {
+        let mut session: SessionStats = SessionStats {
+            bitrates: Vec::with_capacity(self.segment_history.len()),
+            utilities: Vec::with_capacity(self.segment_history.len()),
+            segment_durations: Vec::with_capacity(self.segment_history.len()),
+            rebuffer_time: self.rebuffer_time,
+            switches: 0,
+            switch_magnitude: 0.0,
+        };
+        
+        let mut previous: Option<&SegmentInfo> = None;
+        for segment in &self.segment_history {
+            let utility: f64 = self.quality_utility(segment.quality_level);
+            if let Some(previous) = previous {
+                if previous.quality_level != segment.quality_level {
+                    session.switches += 1;
+                }
+                session.switch_magnitude += (utility - self.quality_utility(previous.quality_level)).abs();
+            }
+            session.bitrates.push(segment.bitrate);
+            session.utilities.push(utility);
+            session.segment_durations.push(segment.duration);
+            previous = Some(segment);
+        }
+        
+        session
+    }
+
+    pub fn qoe_score(&self) -> f64 {
+        self.qoe_model.score(&self.session_stats())
+    }
+
+    pub fn metrics(&self) -> AbrMetrics {
+        AbrMetrics {
+            total_segments: self.total_segments(),
+            total_bytes: self.total_bytes(),
+            rebuffer_count: self.rebuffer_count,
+            rebuffer_time: self.rebuffer_time,
+            up_switches: self.up_switches,
+            down_switches: self.down_switches,
+            quality_distribution: self.time_at_quality.clone(),
+            estimated_bandwidth: self.last_estimated_bandwidth,
+            buffer_level: self.buffer_state.current_level,
+            session_id: self.session_id.clone(),
+            session_elapsed: self.session_elapsed(),
+        }
+    }
+
+    pub fn get_estimated_bandwidth(&self) -> u32 {
+        self.estimate_bandwidth()
+    }
+
+    // Time needed to buffer `min_level` worth of content at the given quality
+    pub fn estimated_startup_delay(&self, initial_quality: usize) -> Option<Duration> {
+        let quality: &QualityLevel = self.quality_levels.get(initial_quality)?;
+        let estimated_bandwidth: u32 = self.estimate_bandwidth();
+        if estimated_bandwidth == 0 {
+            return None;
+        }
+        
+        let required_bytes: f64 = self.buffer_state.min_level.as_secs_f64() * bitrate_to_byte_rate(quality.bitrate) as f64;
+        Some(Duration::from_secs_f64(required_bytes / estimated_bandwidth as f64))
+    }
+
+    // Highest quality the long-run mean bandwidth could carry, ignoring buffer
+    // state and smoothing
+    pub fn max_sustainable_quality(&self) -> usize {
+        if self.lifetime_bandwidth_samples == 0 {
+            return 0;
+        }
+        
+        let mean_bandwidth: u64 = self.lifetime_bandwidth_sum / self.lifetime_bandwidth_samples;
+        self.highest_quality_within(mean_bandwidth.min(u32::MAX as u64) as u32)
+    }
+
+    // Ladder lookup only: no buffer requirements or smoothing
+    fn highest_quality_within(&self, bandwidth: u32) -> usize {
+        self.quality_levels
+            .iter()
+            .rposition(|quality| bitrate_to_byte_rate(quality.bitrate) <= bandwidth)
+            .unwrap_or(0)
+    }
+
+    // Duration-weighted average bitrate of the most recent segments, in bits per second
+    fn recent_played_bitrate(&self) -> f64 {
+        let mut weighted_bitrate: f64 = 0.0;
+        let mut total_duration: f64 = 0.0;
+        
+        for segment in self.segment_history.iter().rev().take(RECENT_SEGMENT_COUNT) {
+            let duration: f64 = segment.duration.as_secs_f64();
+            weighted_bitrate += segment.bitrate as f64 * duration;
+            total_duration += duration;
+        }
+        
+        if total_duration > 0.0 {
+            weighted_bitrate / total_duration
+        } else {
+            0.0
+        }
+    }
+
+    // Above 1.0 the player is requesting more than the link provides
+    pub fn throughput_deficit(&self) -> f64 {
+        let estimated_bitrate: f64 = byte_rate_to_bitrate(self.estimate_bandwidth()) as f64;
+        if estimated_bitrate <= 0.0 {
+            return 0.0;
+        }
+        finite_or(self.recent_played_bitrate() / estimated_bitrate, 0.0)
+    }
+
+    // Share of the bandwidth estimate the played bitrate used, both averaged
+    // over the last RECENT_SEGMENT_COUNT segments and decisions; near 1.0 is
+    // efficient, low is over-cautious. Unlike `throughput_deficit` this
+    // averages the estimate each decision saw instead of taking the latest.
+    pub fn bandwidth_utilization(&self) -> f64 {
+        if self.recent_estimates.is_empty() {
+            return 0.0;
+        }
+        
+        let mean_bitrate: f64 = self.recent_estimates
+            .iter()
+            .map(|&estimate| byte_rate_to_bitrate(estimate) as f64)
+            .sum::<f64>()
+            / self.recent_estimates.len() as f64;
+        if mean_bitrate <= 0.0 {
+            return 0.0;
+        }
+        finite_or(self.recent_played_bitrate() / mean_bitrate, 0.0)
+    }
+
+    // Mean of download_time / duration over segment_history; below 1.0 the
+    // downloads keep up with playback
+    pub fn download_ratio(&self) -> f64 {
+        let ratios: Vec<f64> = self.segment_history
+            .iter()
+            .filter(|segment| !segment.duration.is_zero())
+            .map(|segment| segment.download_time.as_secs_f64() / segment.duration.as_secs_f64())
+            .collect();
+        if ratios.is_empty() {
+            return 0.0;
+        }
+        finite_or(ratios.iter().sum::<f64>() / ratios.len() as f64, 0.0)
+    }
+
+    // Buffer-seconds gained per wall-clock second over the most recent download:
+    // the segment's duration minus what playback drained while it was fetched.
+    // Positive means the buffer is filling.
+    pub fn buffer_rate(&self) -> f64 {
+        let Some(segment) = self.segment_history.back() else {
+            return 0.0;
+        };
+        let span: f64 = segment.download_time.as_secs_f64();
+        if span <= 0.0 {
+            return 0.0;
+        }
+        
+        let span_start: Instant = segment.recorded_at.checked_sub(segment.download_time).unwrap_or(segment.recorded_at);
+        let consumed: f64 = self.consumption_events
+            .iter()
+            .filter(|(timestamp, _)| *timestamp > span_start && *timestamp <= segment.recorded_at)
+            .map(|(_, drained)| drained.as_secs_f64())
+            .sum();
+        
+        finite_or((segment.duration.as_secs_f64() - consumed) / span, 0.0)
+    }
+
+    // Content still needed to reach target_level; zero at or above it
+    pub fn buffer_deficit(&self) -> Duration {
+        self.buffer_state.target_level.saturating_sub(self.buffer_state.current_level)
+    }
+
+    // Content buffered beyond target_level; zero at or below it
+    pub fn buffer_surplus(&self) -> Duration {
+        self.buffer_state.current_level.saturating_sub(self.buffer_state.target_level)
+    }
+
+    // Bytes sitting in the buffer, at the current bitrate; what abandoning the
+    // session now would have wasted
+    pub fn buffer_holding_cost(&self) -> u64 {
+        let byte_rate: f64 = bitrate_to_byte_rate(self.quality_levels[self.current_quality].bitrate) as f64;
+        (byte_rate * self.buffer_state.current_level.as_secs_f64()).round() as u64
+    }
+
+    pub fn is_buffer_healthy(&self) -> bool {
+        self.buffer_state.current_level >= self.buffer_state.min_level
+    }
+
+    pub fn should_pause_playback(&self) -> bool {
+        self.buffer_state.current_level < Duration::from_secs(1)
+    }
+}
+
+fn create_test_quality_levels() -> Vec<QualityLevel> {
+    vec![
+        QualityLevel {
+            bitrate: 500_000,   // 500 kbps
+            width: 640,
+            height: 360,
+            codec: "h264".to_string(),
+        },
+        QualityLevel {
+            bitrate: 1_000_000, // 1 Mbps
+            width: 1280,
+            height: 720,
+            codec: "h264".to_string(),
+        },
+        QualityLevel {
+            bitrate: 2_500_000, // 2.5 Mbps
+            width: 1920,
+            height: 1080,
+            codec: "h264".to_string(),
+        },
+        QualityLevel {
+            bitrate: 5_000_000, // 5 Mbps
+            width: 3840,
+            height: 2160,
+            codec: "h264".to_string(),
+        },
+    ]
+}
+
+// One simulated fetch: the link throughput the segment is downloaded at,
+// whichever rendition the streamer picks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceEvent {
+    pub bandwidth: u32, // bytes per second
+    pub segment_duration: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulationReport {
+    pub config: StreamerConfig,
+    pub decisions: Vec<QualityDecision>,
+    pub average_bitrate: f64, // bits per second, weighted by segment duration
+    pub rebuffer_count: u32,
+    pub rebuffer_time: Duration,
+    pub switches: u32,
+}
+
+// Runs `trace` through a fresh streamer per config on `quality_levels`.
+// Segments are fetched back to back: playback drains the buffer for as long
+// as each download takes. Every config is validated before anything runs, so
+// an invalid one fails the whole comparison.
+pub fn compare_configs(
+    quality_levels: &[QualityLevel],
+    trace: &[TraceEvent],
+    configs: &[StreamerConfig],
+) -> Result<Vec<SimulationReport>, ConfigError> {
+    if quality_levels.is_empty() {
+        return Err(ConfigError::EmptyLadder);
+    }
+    for config in configs {
+        config.validate()?;
+    }
+    
+    // The trace as segments of the lowest rendition; the replay rescales each
+    // one to whatever it picks
+    let reference_bitrate: u32 = quality_levels[0].bitrate;
+    let start: Instant = Instant::now();
+    let segments: Vec<SegmentInfo> = trace
+        .iter()
+        .map(|event| {
+            let size_bytes: u32 =
+                (bitrate_to_byte_rate(reference_bitrate) as f64 * event.segment_duration.as_secs_f64()) as u32;
+            SegmentInfo {
+                quality_level: 0,
+                bitrate: reference_bitrate,
+                size_bytes,
+                duration: event.segment_duration,
+                download_time: Duration::from_secs_f64(size_bytes as f64 / event.bandwidth.max(1) as f64),
+                content_type: ContentType::Main,
+                recorded_at: start,
+                complexity: None,
+            }
+        })
+        .collect();
+    let segments: Vec<&SegmentInfo> = segments.iter().collect();
+    
+    configs
+        .iter()
+        .map(|config| {
+            let mut streamer: AdaptiveBitrateStreamer = AdaptiveBitrateStreamer::with_config(quality_levels.to_vec(), config)?;
+            let decisions: Vec<QualityDecision> = streamer.replay_segments(&segments, None);
+            
+            let played: Duration = streamer.segment_history.iter().map(|segment| segment.duration).sum();
+            let bit_seconds: f64 = streamer.segment_history
+                .iter()
+                .map(|segment| segment.bitrate as f64 * segment.duration.as_secs_f64())
+                .sum();
+            Ok(SimulationReport {
+                config: config.clone(),
+                decisions,
+                average_bitrate: finite_or(bit_seconds / played.as_secs_f64(), 0.0),
+                rebuffer_count: streamer.rebuffer_count(),
+                rebuffer_time: streamer.rebuffer_time(),
+                switches: streamer.up_switches() + streamer.down_switches(),
+            })
+        })
+        .collect()
+}
+
+fn main() {
+    println!("Adaptive Bitrate Streaming Algorithm Demo");
+    
+    let quality_levels: Vec<QualityLevel> = create_test_quality_levels();
+    let mut streamer: AdaptiveBitrateStreamer = AdaptiveBitrateStreamer::new(quality_levels);
+    
+    println!("Initial quality: {} ({}x{} @ {} kbps)", 
+        streamer.current_quality,
+        streamer.get_current_quality().width,
+        streamer.get_current_quality().height,
+        streamer.get_current_quality().bitrate / 1000
+    );
+    
+
+    println!("\nSimulating segment downloads...");
+    
+    streamer.record_segment_download(
+        1_000_000, // 1MB segment
+        Duration::from_millis(800), // Downloaded in 800ms
+        Duration::from_secs(4), // 4-second segment
+    );
+    
+    // strong network
+    let next_quality = streamer.get_next_quality();
+    println!("After fast download - Next quality: {} (estimated bandwidth: {} kbps)", 
+        next_quality,
+        byte_rate_to_bitrate(streamer.get_estimated_bandwidth()) / 1000
+    );
+    
+    // poor network
+    streamer.record_segment_download(
+        500_000, // 500KB segment
+        Duration::from_secs(3), // Downloaded in 3 seconds
+        Duration::from_secs(4), // 4-second segment
+    );
+    
+    let next_quality = streamer.get_next_quality();
+    println!("After slow download - Next quality: {} (estimated bandwidth: {} kbps)", 
+        next_quality,
+        byte_rate_to_bitrate(streamer.get_estimated_bandwidth()) / 1000
+    );
+    
+    let buffer = streamer.get_buffer_state();
+    println!("\nBuffer state:");
+    println!("  Current level: {:.1}s", buffer.current_level.as_secs_f64());
+    println!("  Target level: {:.1}s", buffer.target_level.as_secs_f64());
+    println!("  Buffer healthy: {}", streamer.is_buffer_healthy());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    #[derive(Clone)]
+    struct MockClock {
+        now: Rc<Cell<Instant>>,
+    }
+
+    impl MockClock {
+        fn new() -> Self {
+            Self { now: Rc::new(Cell::new(Instant::now())) }
+        }
+
+        fn advance(&self, duration: Duration) {
+            self.now.set(self.now.get() + duration);
+        }
+    }
+
+    impl AdaptiveBitrateStreamer {
+        // A streamer already in the state a test needs, instead of recording
+        // segments to get there
+        fn for_test(
+            quality_levels: Vec<QualityLevel>,
+            initial_quality: usize,
+            buffer_state: BufferState,
+            params: StreamerConfig,
+        ) -> Self {
+            assert!(initial_quality < quality_levels.len());
+            let mut streamer: AdaptiveBitrateStreamer =
+                AdaptiveBitrateStreamer::with_config(quality_levels, &params).expect("valid test params");
+            streamer.current_quality = initial_quality;
+            streamer.buffer_state = buffer_state;
+            streamer.last_buffer_zone = streamer.buffer_zone();
+            streamer
+        }
+        
+        // Bandwidth samples taken now, in bytes per second, as if measured by
+        // downloads that never touched the buffer or segment history
+        fn with_bandwidth_samples(mut self, samples: &[u32]) -> Self {
+            let now: Instant = self.clock.now();
+            for &bandwidth in samples {
+                self.bandwidth_history.push_back((now, bandwidth));
+                self.lifetime_bandwidth_sum += bandwidth as u64;
+                self.lifetime_bandwidth_samples += 1;
+                self.peak_bandwidth = self.peak_bandwidth.max(bandwidth);
+                self.first_sample = self.first_sample.or(Some((now, bandwidth)));
+            }
+            self
+        }
+    }
+
+    // Default thresholds with the buffer at `level`
+    fn buffer_at(level: Duration) -> BufferState {
+        BufferState {
+            current_level: level,
+            target_level: Duration::from_secs(30),
+            max_level: Duration::from_secs(60),
+            min_level: Duration::from_secs(5),
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            self.now.get()
+        }
+    }
+
+    #[test]
+    fn test_initial_quality_selection() {
+        let quality_levels = create_test_quality_levels();
+        let streamer = AdaptiveBitrateStreamer::new(quality_levels);
+        
+        // Should start with middle quality (index 2 for 4 levels)
+        assert_eq!(streamer.current_quality, 2);
+    }
+
+    #[test]
+    fn test_bandwidth_recording() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        
+        streamer.record_segment_download(
+            1_000_000, // 1MB segment
+            Duration::from_secs(1), // Downloaded in 1 second
+            Duration::from_secs(4), // 4-second segment
+        );
+        
+        assert_eq!(streamer.bandwidth_history.len(), 1);
+        assert_eq!(streamer.segment_history.len(), 1);
+    }
+
+    #[test]
+    fn test_min_buffer_per_quality() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_min_buffer_for_quality(3, Duration::from_secs(10)).unwrap();
+        
+        // Plenty of bandwidth for the top quality
+        let available_bandwidth: u32 = 10_000_000;
+        
+        streamer.buffer_state.current_level = Duration::from_secs(8);
+        assert_eq!(streamer.find_suitable_quality(available_bandwidth), 2);
+        
+        streamer.buffer_state.current_level = Duration::from_secs(12);
+        assert_eq!(streamer.find_suitable_quality(available_bandwidth), 3);
+        
+        // Already playing the top quality doesn't exempt it: 2s is below its
+        // 10s minimum, so the decision steps down. Unset entries don't gate,
+        // so the lower renditions stay selectable under min_level.
+        streamer.current_quality = 3;
+        streamer.buffer_state.current_level = Duration::from_secs(2);
+        assert_eq!(streamer.find_suitable_quality(available_bandwidth), 2);
+        for _ in 0..3 {
+            streamer.record_segment_download(2_500_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        streamer.buffer_state.current_level = Duration::from_secs(8);
+        assert!(streamer.get_next_quality() < 3);
+        
+        // Unset entries fall back to the global min_level
+        assert_eq!(streamer.min_buffer_for_quality(1), streamer.buffer_state.min_level);
+        
+        // Without a table, a buffer under min_level doesn't force the lowest rendition
+        let mut defaults = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        defaults.buffer_state.current_level = Duration::from_secs(3);
+        assert_eq!(defaults.find_suitable_quality(2_500_000), 3);
+        assert_eq!(
+            streamer.set_min_buffer_for_quality(4, Duration::from_secs(1)),
+            Err(ConfigError::QualityIndexOutOfRange(4))
+        );
+    }
+
+    #[test]
+    fn test_neutral_utilization_layers() {
+        let mut defaults = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        let mut neutral = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        neutral.set_utilization_layers(UtilizationLayers {
+            min_of_estimators: false,
+            buffer_factor: false,
+            safety_factor: false,
+        });
+        
+        for streamer in [&mut defaults, &mut neutral] {
+            for _ in 0..3 {
+                streamer.record_segment_download(
+                    400_000, // 400KB segment, 3.2 Mbps
+                    Duration::from_secs(1),
+                    Duration::from_secs(4),
+                );
+            }
+            streamer.buffer_state.current_level = Duration::from_secs(10);
+        }
+        
+        // Below target buffer: 11/15 buffer factor times 0.8 safety factor
+        assert!((defaults.effective_utilization() - 0.8 * 11.0 / 15.0).abs() < 1e-6);
+        assert_eq!(neutral.effective_utilization(), 1.0);
+        
+        // Neutral layers keep 2.5 Mbps, the defaults step down to 1 Mbps
+        assert_eq!(neutral.get_next_quality(), 2);
+        assert_eq!(defaults.get_next_quality(), 1);
+    }
+
+    #[test]
+    fn test_estimated_startup_delay() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        for _ in 0..3 {
+            streamer.record_segment_download(
+                250_000, // 2 Mbps
+                Duration::from_secs(1),
+                Duration::from_secs(4),
+            );
+        }
+        
+        // 5s min buffer: 312.5KB at 500 kbps, 3125KB at 5 Mbps
+        let lowest = streamer.estimated_startup_delay(0).unwrap();
+        let highest = streamer.estimated_startup_delay(3).unwrap();
+        assert!((lowest.as_secs_f64() - 1.25).abs() < 0.01);
+        assert!((highest.as_secs_f64() - 12.5).abs() < 0.01);
+        assert!(lowest < highest);
+        
+        assert_eq!(streamer.estimated_startup_delay(4), None);
+    }
+
+    #[test]
+    fn test_content_type_segment_stats() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        
+        streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+        streamer.record_segment_download_tagged(
+            100_000,
+            Duration::from_millis(200),
+            Duration::from_secs(1),
+            ContentType::Ad,
+        );
+        streamer.record_segment_download_tagged(
+            100_000,
+            Duration::from_millis(200),
+            Duration::from_secs(1),
+            ContentType::Ad,
+        );
+        streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(6));
+        
+        assert_eq!(streamer.average_segment_duration(), Some(Duration::from_secs(5)));
+        
+        let ad_stats = streamer.content_stats(ContentType::Ad);
+        assert_eq!(ad_stats.segment_count, 2);
+        assert_eq!(ad_stats.total_bytes, 200_000);
+        assert_eq!(ad_stats.average_segment_duration(), Some(Duration::from_secs(1)));
+        
+        // Buffer and segment history still include the ad segments
+        assert_eq!(streamer.segment_history.len(), 4);
+        assert_eq!(streamer.get_buffer_state().current_level, Duration::from_secs(12));
+    }
+
+    #[test]
+    fn test_seeking_factor_decays_without_headroom() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.buffer_state.current_level = Duration::from_secs(50);
+        assert_eq!(streamer.calculate_buffer_factor(), 1.5);
+        
+        // 350KB/s covers the 2.5 Mbps quality but not 1.5x of it
+        let mut previous_factor = streamer.calculate_buffer_factor();
+        for _ in 0..5 {
+            streamer.record_segment_download(350_000, Duration::from_secs(1), Duration::from_secs(4));
+            let factor = streamer.calculate_buffer_factor();
+            assert!(factor < previous_factor);
+            previous_factor = factor;
+        }
+        assert!((previous_factor - 1.0).abs() < 1e-9);
+        
+        // Throughput confirming the headroom restores the full factor
+        streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+        assert_eq!(streamer.calculate_buffer_factor(), 1.5);
+    }
+
+    #[test]
+    fn test_max_sustainable_quality() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        assert_eq!(streamer.max_sustainable_quality(), 0);
+        
+        // Mean of 200KB/s and 500KB/s is 350KB/s: enough for 2.5 Mbps, not 5 Mbps
+        streamer.record_segment_download(200_000, Duration::from_secs(1), Duration::from_secs(4));
+        streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+        assert_eq!(streamer.max_sustainable_quality(), 2);
+        
+        // Buffer state does not matter
+        streamer.buffer_state.current_level = Duration::from_secs(0);
+        assert_eq!(streamer.max_sustainable_quality(), 2);
+    }
+
+    #[test]
+    fn test_metrics_snapshot() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        
+        streamer.record_segment_download(300_000, Duration::from_secs(1), Duration::from_secs(4));
+        streamer.update_buffer_consumption(Duration::from_secs(6)); // 2s stall
+        streamer.record_segment_download(100_000, Duration::from_secs(1), Duration::from_secs(4));
+        streamer.record_segment_download(200_000, Duration::from_secs(1), Duration::from_secs(4));
+        streamer.get_next_quality();
+        streamer.record_segment_download(400_000, Duration::from_secs(1), Duration::from_secs(4));
+        streamer.get_next_quality();
+        
+        let metrics = streamer.metrics();
+        assert_eq!(metrics.total_segments, streamer.total_segments());
+        assert_eq!(metrics.total_bytes, streamer.total_bytes());
+        assert_eq!(metrics.rebuffer_count, streamer.rebuffer_count());
+        assert_eq!(metrics.rebuffer_time, streamer.rebuffer_time());
+        assert_eq!(metrics.up_switches, streamer.up_switches());
+        assert_eq!(metrics.down_switches, streamer.down_switches());
+        assert_eq!(metrics.quality_distribution, streamer.quality_distribution());
+        assert_eq!(metrics.estimated_bandwidth, streamer.get_estimated_bandwidth());
+        assert_eq!(metrics.buffer_level, streamer.get_buffer_state().current_level);
+        
+        assert_eq!(metrics.total_segments, 4);
+        assert_eq!(metrics.total_bytes, 1_000_000);
+        assert_eq!(metrics.rebuffer_count, 1);
+        assert_eq!(metrics.rebuffer_time, Duration::from_secs(2));
+        assert_eq!(metrics.down_switches, 2);
+        assert_eq!(metrics.quality_distribution.iter().sum::<Duration>(), Duration::from_secs(16));
+    }
+
+    #[test]
+    fn test_configurable_panic_factor() {
+        let mut default_panic = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        let mut hard_panic = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        hard_panic.set_panic_factor(0.1).unwrap();
+        
+        for streamer in [&mut default_panic, &mut hard_panic] {
+            streamer.set_utilization_layers(UtilizationLayers {
+                safety_factor: false,
+                ..UtilizationLayers::default()
+            });
+            for _ in 0..3 {
+                streamer.record_segment_download(1_000_000, Duration::from_secs(1), Duration::from_secs(4));
+            }
+            streamer.buffer_state.current_level = Duration::from_secs(1);
+        }
+        
+        assert_eq!(hard_panic.effective_utilization(), 0.1);
+        
+        // 300KB/s still fits 1 Mbps, 100KB/s only fits 500 kbps
+        assert_eq!(default_panic.get_next_quality_detailed().target_quality, 1);
+        assert_eq!(hard_panic.get_next_quality_detailed().target_quality, 0);
+        
+        for invalid in [0.0, 1.0, 1.5, f64::NAN] {
+            assert!(hard_panic.set_panic_factor(invalid).is_err());
+        }
+        assert_eq!(hard_panic.effective_utilization(), 0.1);
+    }
+
+    #[test]
+    fn test_throughput_deficit() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        assert_eq!(streamer.throughput_deficit(), 0.0);
+        
+        // Requesting 5 Mbps over a 2 Mbps link
+        streamer.current_quality = 3;
+        for _ in 0..3 {
+            streamer.record_segment_download(250_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        assert!(streamer.throughput_deficit() > 1.0);
+        assert!((streamer.throughput_deficit() - 2.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_decision_reports_clamped_target() {
+        let mut streamer = AdaptiveBitrateStreamer::for_test(
+            create_test_quality_levels(),
+            1,
+            buffer_at(Duration::from_secs(35)),
+            StreamerConfig::default(),
+        )
+        .with_bandwidth_samples(&[5_000_000; 3]);
+        
+        // 40 Mbps supports well above the top quality, smoothing allows one step
+        let decision = streamer.get_next_quality_detailed();
+        assert_eq!(decision.target_quality, 3);
+        assert_eq!(decision.quality, 2);
+        assert_eq!(decision.clamped_from, Some(3));
+        
+        // At the top, nothing is held back
+        streamer.get_next_quality();
+        let decision = streamer.get_next_quality_detailed();
+        assert_eq!(decision.quality, 3);
+        assert_eq!(decision.clamped_from, None);
+        
+        // A deadline holds the decision below target, but it isn't a smoothing clamp
+        streamer.set_next_segment_deadline(Some(Instant::now()));
+        let decision = streamer.get_next_quality_detailed();
+        assert_eq!(decision.target_quality, 3);
+        assert_eq!(decision.quality, 0);
+        assert_eq!(decision.clamped_from, None);
+    }
+
+    #[test]
+    fn test_network_class_initial_quality() {
+        let mut cellular = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        let mut ethernet = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        cellular.set_network_class(NetworkClass::Cellular);
+        ethernet.set_network_class(NetworkClass::Ethernet);
+        
+        assert_eq!(cellular.current_quality, 1);
+        assert_eq!(ethernet.current_quality, 3);
+        assert!(cellular.get_estimated_bandwidth() < ethernet.get_estimated_bandwidth());
+        
+        // Once enough samples exist the class no longer matters
+        for streamer in [&mut cellular, &mut ethernet] {
+            for segment_size in [200_000, 300_000, 400_000] {
+                streamer.record_segment_download(segment_size, Duration::from_secs(1), Duration::from_secs(4));
+            }
+        }
+        assert_eq!(cellular.get_estimated_bandwidth(), ethernet.get_estimated_bandwidth());
+    }
+
+    #[test]
+    fn test_deadline_limits_quality() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        for _ in 0..3 {
+            streamer.record_segment_download(1_000_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        streamer.current_quality = 3;
+        streamer.buffer_state.current_level = Duration::from_secs(35);
+        assert_eq!(streamer.get_next_quality(), 3);
+        
+        // 4s segments at 1MB/s: 2.5s for 5 Mbps, 1.25s for 2.5 Mbps.
+        // A 2s deadline with the 500ms margin only leaves room for 2.5 Mbps.
+        streamer.set_next_segment_deadline(Some(Instant::now() + Duration::from_secs(2)));
+        assert_eq!(streamer.get_next_quality(), 2);
+        
+        assert_eq!(streamer.deadline_misses(), 0);
+        let past_deadline = Instant::now() - Duration::from_millis(100);
+        streamer.record_segment_download_with_deadline(
+            1_000_000,
+            Duration::from_secs(1),
+            Duration::from_secs(4),
+            Some(past_deadline),
+        );
+        assert_eq!(streamer.deadline_misses(), 1);
+    }
+
+    #[test]
+    fn test_closest_quality_to_bitrate() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        
+        assert_eq!(streamer.closest_quality_to_bitrate(1_200_000), 1);
+        assert_eq!(streamer.closest_quality_to_bitrate(2_000_000), 2);
+        assert_eq!(streamer.closest_quality_to_bitrate(10_000_000), 3);
+        assert_eq!(streamer.closest_quality_to_bitrate(0), 0);
+        // Exactly between 1 Mbps and 2.5 Mbps resolves to the lower level
+        assert_eq!(streamer.closest_quality_to_bitrate(1_750_000), 1);
+        
+        // Updating the ladder keeps the nearest bitrate to the one playing
+        let mut levels = create_test_quality_levels();
+        levels.remove(0);
+        streamer.update_quality_levels(levels).unwrap();
+        assert_eq!(streamer.get_current_quality().bitrate, 2_500_000);
+        assert_eq!(streamer.update_quality_levels(Vec::new()), Err(ConfigError::EmptyLadder));
+    }
+
+    #[test]
+    fn test_quality_utilities() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        
+        assert_eq!(streamer.quality_utility(0), 0.0);
+        for i in 1..4 {
+            assert!(streamer.quality_utility(i) > streamer.quality_utility(i - 1));
+        }
+        assert!((streamer.quality_utility(1) - 2.0_f64.ln()).abs() < 1e-9);
+        
+        // Recomputed against the new lowest level when the ladder changes
+        let mut levels = create_test_quality_levels();
+        levels.remove(0);
+        streamer.update_quality_levels(levels).unwrap();
+        assert_eq!(streamer.quality_utility(0), 0.0);
+        assert!((streamer.quality_utility(1) - 2.5_f64.ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_empirical_segment_size() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        
+        // 4s at 2.5 Mbps implies 1.25MB, the VBR segments come in at 800KB
+        for _ in 0..3 {
+            streamer.record_segment_download(800_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        assert_eq!(streamer.estimated_segment_size(2), 800_000);
+        
+        // No samples at 5 Mbps, so bitrate x average duration is used
+        assert_eq!(streamer.estimated_segment_size(3), 2_500_000);
+        
+        streamer.set_empirical_segment_sizes(false);
+        assert_eq!(streamer.estimated_segment_size(2), 1_250_000);
+    }
+
+    #[test]
+    fn test_freeze_detection() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        streamer.set_freeze_timeout(Duration::from_secs(1));
+        streamer.buffer_state.current_level = Duration::from_secs(20);
+        
+        streamer.report_playback_progress(Duration::from_secs(10));
+        clock.advance(Duration::from_millis(500));
+        streamer.report_playback_progress(Duration::from_millis(10_500));
+        assert!(!streamer.is_frozen());
+        
+        // Playhead stuck with a healthy buffer
+        clock.advance(Duration::from_millis(800));
+        streamer.report_playback_progress(Duration::from_millis(10_500));
+        assert!(!streamer.is_frozen());
+        clock.advance(Duration::from_millis(800));
+        streamer.report_playback_progress(Duration::from_millis(10_500));
+        assert!(streamer.is_frozen());
+        
+        // Advancing again clears the flag
+        clock.advance(Duration::from_millis(100));
+        streamer.report_playback_progress(Duration::from_millis(10_600));
+        assert!(!streamer.is_frozen());
+        
+        // An empty buffer is a rebuffer, not a freeze
+        streamer.buffer_state.current_level = Duration::from_secs(0);
+        clock.advance(Duration::from_secs(5));
+        streamer.report_playback_progress(Duration::from_millis(10_600));
+        assert!(!streamer.is_frozen());
+    }
+
+    #[test]
+    fn test_seek_threshold_ratio() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.buffer_state.max_level = Duration::from_secs(20);
+        streamer.buffer_state.target_level = Duration::from_secs(10);
+        
+        // The fixed 45s threshold can never be reached with a 20s max_level
+        streamer.buffer_state.current_level = Duration::from_secs(20);
+        assert_eq!(streamer.calculate_buffer_factor(), 1.0);
+        
+        streamer.set_seek_threshold_ratio(0.8).unwrap();
+        assert_eq!(streamer.seek_threshold(), Duration::from_secs(16));
+        
+        streamer.buffer_state.current_level = Duration::from_millis(15_900);
+        assert_eq!(streamer.calculate_buffer_factor(), 1.0);
+        streamer.buffer_state.current_level = Duration::from_millis(16_100);
+        assert_eq!(streamer.calculate_buffer_factor(), 1.5);
+        
+        assert!(streamer.set_seek_threshold_ratio(1.2).is_err());
+        assert!(streamer.set_seek_threshold_ratio(0.0).is_err());
+    }
+
+    #[test]
+    fn test_windowed_qoe_ignores_early_stall() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        let weights = QoeWeights::default();
+        
+        streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+        streamer.update_buffer_consumption(Duration::from_secs(10)); // 6s stall
+        
+        clock.advance(Duration::from_secs(60));
+        for _ in 0..5 {
+            streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+            clock.advance(Duration::from_secs(1));
+        }
+        
+        // Five clean 4s segments at 2.5 Mbps: utility ln(5) per second
+        let clean_qoe = 5.0 * 4.0 * 5.0_f64.ln();
+        let windowed = streamer.compute_windowed_qoe(Duration::from_secs(30), weights);
+        assert!((windowed - clean_qoe).abs() < 1e-9);
+        
+        let session = streamer.compute_qoe(weights);
+        assert!((session - (clean_qoe + 4.0 * 5.0_f64.ln() - 6.0 * weights.rebuffer)).abs() < 1e-9);
+        assert!(session < windowed);
+    }
+
+    #[test]
+    fn test_required_samples_follow_variance() {
+        let mut stable = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        let mut volatile = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        
+        for segment_size in [300_000, 310_000] {
+            stable.record_segment_download(segment_size, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        for segment_size in [100_000, 600_000, 100_000] {
+            volatile.record_segment_download(segment_size, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        
+        assert_eq!(stable.required_samples(), 2);
+        assert!(stable.has_confident_estimate());
+        
+        assert_eq!(volatile.required_samples(), 6);
+        assert!(!volatile.has_confident_estimate());
+    }
+
+    #[test]
+    fn test_ladder_bits_per_pixel_validation() {
+        let level = QualityLevel {
+            bitrate: 1_000_000,
+            width: 1000,
+            height: 100,
+            codec: "h264".to_string(),
+        };
+        assert!((level.bits_per_pixel(10.0) - 1.0).abs() < 1e-9);
+        
+        let ladder = vec![
+            QualityLevel {
+                bitrate: 1_000_000,
+                width: 640,
+                height: 360,
+                codec: "h264".to_string(),
+            },
+            QualityLevel {
+                bitrate: 2_000_000,
+                width: 640,
+                height: 360,
+                codec: "h264".to_string(),
+            },
+            // Four times the pixels for barely more bitrate
+            QualityLevel {
+                bitrate: 2_100_000,
+                width: 1280,
+                height: 720,
+                codec: "h264".to_string(),
+            },
+        ];
+        let streamer = AdaptiveBitrateStreamer::new(ladder);
+        
+        let warnings = streamer.validate_ladder_quality();
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(warnings[0], LadderWarning::BitsPerPixelInversion { index: 2, .. }));
+    }
+
+    #[test]
+    fn test_decision_log_keeps_most_recent() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.get_next_quality();
+        assert!(streamer.decision_log().is_empty());
+        
+        streamer.set_decision_log_capacity(3);
+        let mut buffer_levels = Vec::new();
+        for _ in 0..5 {
+            streamer.record_segment_download(300_000, Duration::from_secs(1), Duration::from_secs(4));
+            buffer_levels.push(streamer.get_buffer_state().current_level);
+            streamer.get_next_quality();
+        }
+        
+        let log = streamer.decision_log();
+        assert_eq!(log.len(), 3);
+        let logged_levels: Vec<Duration> = log.iter().map(|entry| entry.input.buffer_level).collect();
+        assert_eq!(logged_levels, buffer_levels[2..]);
+        assert_eq!(log[2].decision.quality, streamer.current_quality);
+        
+        streamer.set_decision_log_capacity(1);
+        assert_eq!(streamer.decision_log().len(), 1);
+        assert_eq!(streamer.decision_log()[0].input.buffer_level, buffer_levels[4]);
+    }
+
+    #[test]
+    fn test_bandwidth_floor_keeps_predictions_finite() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        for _ in 0..3 {
+            streamer.record_segment_download(10, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        assert_eq!(streamer.get_estimated_bandwidth(), 1_000);
+        
+        // 4s at 500 kbps is 250KB, so 250s at the floor
+        let download_time = streamer.predicted_download_time(0);
+        assert!((download_time.as_secs_f64() - 250.0).abs() < 1e-6);
+        
+        streamer.set_min_bandwidth_floor(5_000).unwrap();
+        assert_eq!(streamer.get_estimated_bandwidth(), 5_000);
+        assert!(streamer.set_min_bandwidth_floor(0).is_err());
+    }
+
+    #[test]
+    fn test_upswitch_buffer_safety() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_utilization_layers(UtilizationLayers {
+            min_of_estimators: true,
+            buffer_factor: false,
+            safety_factor: false,
+        });
+        streamer.set_upswitch_buffer_safety(true);
+        
+        // 700KB/s with 10s segments: a 5 Mbps segment takes ~8.9s to fetch
+        for _ in 0..3 {
+            streamer.record_segment_download(7_000_000, Duration::from_secs(10), Duration::from_secs(10));
+        }
+        
+        streamer.buffer_state.current_level = Duration::from_secs(8);
+        let decision = streamer.get_next_quality_detailed();
+        assert_eq!(decision.target_quality, 3);
+        assert_eq!(decision.quality, 2);
+        
+        streamer.buffer_state.current_level = Duration::from_secs(10);
+        assert_eq!(streamer.get_next_quality(), 3);
+    }
+
+    #[test]
+    fn test_no_feasible_quality_below_lowest_bitrate() {
+        let streamer = AdaptiveBitrateStreamer::for_test(
+            create_test_quality_levels(),
+            0,
+            buffer_at(Duration::from_secs(35)),
+            StreamerConfig::default(),
+        )
+        .with_bandwidth_samples(&[20_000; 3]);
+        
+        // 20KB/s is well below the 62.5KB/s the lowest rendition needs
+        assert!(!streamer.has_feasible_quality());
+        assert_eq!(streamer.find_suitable_quality(streamer.effective_bandwidth()), 0);
+        
+        let healthy = AdaptiveBitrateStreamer::for_test(
+            create_test_quality_levels(),
+            0,
+            buffer_at(Duration::from_secs(35)),
+            StreamerConfig::default(),
+        )
+        .with_bandwidth_samples(&[200_000; 3]);
+        assert!(healthy.has_feasible_quality());
+    }
+
+    #[test]
+    fn test_buffer_full_policies() {
+        let policies = [
+            (BufferFullPolicy::Clamp, Duration::from_secs(60)),
+            (BufferFullPolicy::RejectDownload, Duration::from_secs(58)),
+            (BufferFullPolicy::IncreaseMax, Duration::from_secs(62)),
+        ];
+        
+        for (policy, expected_level) in policies {
+            let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+            streamer.set_buffer_full_policy(policy);
+            streamer.buffer_state.current_level = Duration::from_secs(50);
+            
+            assert!(!streamer.record_segment_download(300_000, Duration::from_secs(1), Duration::from_secs(8)));
+            assert!(streamer.record_segment_download(300_000, Duration::from_secs(1), Duration::from_secs(4)));
+            assert_eq!(streamer.get_buffer_state().current_level, expected_level);
+            
+            // Only a rejected segment stays out of the history
+            let expected_segments: usize = if policy == BufferFullPolicy::RejectDownload { 1 } else { 2 };
+            assert_eq!(streamer.segment_history.len(), expected_segments);
+            assert_eq!(streamer.bandwidth_history.len(), 2);
+        }
+    }
+
+    struct SwitchAverseQoe;
+
+    impl QoeModel for SwitchAverseQoe {
+        fn score(&self, session: &SessionStats) -> f64 {
+            let utility: f64 = session.utilities.iter().sum();
+            utility - 1000.0 * session.switches as f64 - 4.3 * session.rebuffer_time.as_secs_f64()
+        }
+    }
+
+    #[test]
+    fn test_mpc_uses_active_qoe_model() {
+        let mut linear = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        let mut switch_averse = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        switch_averse.set_qoe_model(Box::new(SwitchAverseQoe));
+        
+        for streamer in [&mut linear, &mut switch_averse] {
+            streamer.set_decision_mode(DecisionMode::Mpc { horizon: 3 });
+            for _ in 0..3 {
+                streamer.record_segment_download(1_500_000, Duration::from_secs(1), Duration::from_secs(4));
+            }
+            streamer.current_quality = 0;
+            streamer.buffer_state.current_level = Duration::from_secs(35);
+            for _ in 0..3 {
+                streamer.get_next_quality();
+            }
+        }
+        
+        // 12 Mbps comfortably carries the top quality without rebuffering
+        assert_eq!(linear.current_quality, 3);
+        assert_eq!(linear.up_switches(), 1);
+        
+        assert_eq!(switch_averse.current_quality, 0);
+        assert_eq!(switch_averse.up_switches() + switch_averse.down_switches(), 0);
+    }
+
+    #[test]
+    fn test_mpc_search_stays_bounded_on_long_ladders() {
+        let levels: Vec<QualityLevel> = (1..=30)
+            .map(|i| QualityLevel {
+                bitrate: i * 100_000,
+                width: 1280,
+                height: 720,
+                codec: "h264".to_string(),
+            })
+            .collect();
+        let mut streamer = AdaptiveBitrateStreamer::new(levels);
+        streamer.set_decision_mode(DecisionMode::Mpc { horizon: MAX_MPC_HORIZON });
+        for _ in 0..3 {
+            streamer.record_segment_download(1_500_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        streamer.buffer_state.current_level = Duration::from_secs(35);
+        
+        // 30^5 plans would take minutes; the first step can still jump to the top
+        assert_eq!(streamer.get_next_quality(), 29);
+    }
+
+    #[test]
+    fn test_linear_qoe_matches_compute_qoe() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+        streamer.current_quality = 3;
+        streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+        streamer.update_buffer_consumption(Duration::from_secs(10));
+        
+        let expected = streamer.compute_qoe(QoeWeights::default());
+        assert!((streamer.qoe_score() - expected).abs() < 1e-9);
+        assert_eq!(streamer.session_stats().switches, 1);
+    }
+
+    #[test]
+    fn test_buffer_rate() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        streamer.buffer_state.current_level = Duration::from_secs(20);
+        assert_eq!(streamer.buffer_rate(), 0.0);
+        
+        // 4s segment fetched in 4s while 4s played: the buffer holds steady
+        for _ in 0..2 {
+            clock.advance(Duration::from_secs(2));
+            streamer.update_buffer_consumption(Duration::from_secs(2));
+        }
+        streamer.record_segment_download(500_000, Duration::from_secs(4), Duration::from_secs(4));
+        assert!(streamer.buffer_rate().abs() < 1e-9);
+        
+        // Fetched in half the time: +4s added, -2s played over 2s of wall clock
+        for _ in 0..2 {
+            clock.advance(Duration::from_secs(1));
+            streamer.update_buffer_consumption(Duration::from_secs(1));
+        }
+        streamer.record_segment_download(500_000, Duration::from_secs(2), Duration::from_secs(4));
+        assert!((streamer.buffer_rate() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_seed_bandwidth_history() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        let default_estimate = streamer.get_estimated_bandwidth();
+        assert!(!streamer.has_confident_estimate());
+        
+        streamer.seed_bandwidth_history(&[
+            (Duration::from_secs(1), 450_000),
+            (Duration::from_secs(3), 400_000),
+            (Duration::from_secs(2), 500_000),
+            (Duration::from_secs(30), 50_000), // outside the window
+        ]);
+        
+        assert_eq!(streamer.bandwidth_history.len(), 3);
+        assert!(streamer.has_confident_estimate());
+        assert_ne!(streamer.get_estimated_bandwidth(), default_estimate);
+        assert_eq!(streamer.get_estimated_bandwidth(), 400_000);
+    }
+
+    #[test]
+    fn test_mode_smoothed_decisions() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_decision_mode(DecisionMode::ModeSmoothed { window: 5 });
+        streamer.current_quality = 1;
+        
+        // Raw targets flip between 3 and 1, with 3 dominating
+        let mut committed = Vec::new();
+        for target in [3, 1, 3, 1, 3, 3, 1, 3] {
+            let quality = streamer.mode_smoothed_quality(target, 5);
+            streamer.current_quality = quality;
+            committed.push(quality);
+        }
+        assert_eq!(committed, vec![3, 3, 3, 3, 3, 3, 3, 3]);
+        
+        // The mode only gives way once the other quality dominates the window
+        let mut committed = Vec::new();
+        for target in [1, 1, 1] {
+            let quality = streamer.mode_smoothed_quality(target, 5);
+            streamer.current_quality = quality;
+            committed.push(quality);
+        }
+        assert_eq!(committed, vec![3, 1, 1]);
+        
+        // Wired into the regular decision path
+        for _ in 0..3 {
+            streamer.record_segment_download(200_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        streamer.buffer_state.current_level = Duration::from_secs(35);
+        let decision = streamer.get_next_quality_detailed();
+        assert_eq!(decision.target_quality, 1);
+        assert_eq!(decision.quality, 1);
+    }
+
+    #[test]
+    fn test_shrinking_bandwidth_window_evicts_samples() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.seed_bandwidth_history(&[
+            (Duration::from_secs(9), 100_000),
+            (Duration::from_secs(6), 200_000),
+            (Duration::from_secs(2), 300_000),
+        ]);
+        assert_eq!(streamer.bandwidth_window(), Duration::from_secs(10));
+        assert_eq!(streamer.bandwidth_history.len(), 3);
+        
+        streamer.set_bandwidth_window(Duration::from_secs(5)).unwrap();
+        assert_eq!(streamer.bandwidth_window(), Duration::from_secs(5));
+        assert_eq!(streamer.bandwidth_history.len(), 1);
+        assert_eq!(streamer.bandwidth_history[0].1, 300_000);
+        
+        assert!(streamer.set_bandwidth_window(Duration::from_secs(0)).is_err());
+    }
+
+    #[test]
+    fn test_suggested_preemptive_quality() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.current_quality = 3;
+        for _ in 0..3 {
+            streamer.record_segment_download(2_400_000, Duration::from_secs(8), Duration::from_secs(4));
+        }
+        streamer.buffer_state.current_level = Duration::from_secs(10);
+        
+        // 300KB/s: 5 Mbps segments take 8s, 2.5 Mbps needs 312.5KB/s to keep up
+        let time_to_rebuffer = streamer.time_to_rebuffer().unwrap();
+        assert!(time_to_rebuffer > Duration::from_secs(10));
+        assert_eq!(streamer.suggested_preemptive_quality(), Some(1));
+        
+        // Nothing is suggested while downloads keep up
+        streamer.current_quality = 1;
+        assert_eq!(streamer.time_to_rebuffer(), None);
+        assert_eq!(streamer.suggested_preemptive_quality(), None);
+    }
+
+    #[test]
+    fn test_bandwidth_cv() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        assert_eq!(streamer.bandwidth_cv(), 0.0);
+        streamer.record_segment_download(300_000, Duration::from_secs(1), Duration::from_secs(4));
+        assert_eq!(streamer.bandwidth_cv(), 0.0);
+        
+        for _ in 0..4 {
+            streamer.record_segment_download(300_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        assert!(streamer.bandwidth_cv() < 1e-9);
+        
+        let mut volatile = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        for segment_size in [50_000, 900_000, 100_000, 1_000_000] {
+            volatile.record_segment_download(segment_size, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        assert!(volatile.bandwidth_cv() > 0.5);
+    }
+
+    #[test]
+    fn test_panic_soft_landing() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.current_quality = 0;
+        for _ in 0..3 {
+            streamer.record_segment_download(800_000, Duration::from_secs(4), Duration::from_secs(4));
+        }
+        streamer.current_quality = 3;
+        streamer.buffer_state.current_level = Duration::from_secs(2);
+        
+        // 200KB/s in panic only budgets 48KB/s, but 1 Mbps segments still
+        // download in 2.5s
+        let decision = streamer.get_next_quality_detailed();
+        assert_eq!(decision.target_quality, 0);
+        assert_eq!(decision.quality, 1);
+        
+        // Nearly empty buffer: emergency drop straight to the target
+        streamer.current_quality = 3;
+        streamer.buffer_state.current_level = Duration::from_millis(500);
+        assert_eq!(streamer.get_next_quality(), 0);
+    }
+
+    #[test]
+    fn test_quality_level_lookup() {
+        let streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        
+        assert_eq!(streamer.quality_level(0).map(|quality| quality.bitrate), Some(500_000));
+        assert_eq!(streamer.quality_level(3).map(|quality| quality.height), Some(2160));
+        assert!(streamer.quality_level(4).is_none());
+    }
+
+    #[test]
+    fn test_time_bucketing_collapses_bursts() {
+        let mut plain = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        let mut bucketed = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        bucketed.set_time_bucketing(Some(Duration::from_secs(1)));
+        
+        let mut samples = vec![
+            (Duration::from_secs(5), 100_000),
+            (Duration::from_secs(3), 100_000),
+        ];
+        // Eight samples in a single 400ms burst
+        for i in 0..8 {
+            samples.push((Duration::from_millis(400 - i * 50), 1_000_000));
+        }
+        
+        for streamer in [&mut plain, &mut bucketed] {
+            streamer.min_bandwidth_samples = 1;
+            streamer.seed_bandwidth_history(&samples);
+        }
+        
+        assert_eq!(bucketed.estimation_samples().len(), 3);
+        assert_eq!(plain.estimation_samples().len(), 10);
+        
+        // The burst drags the harmonic mean up without bucketing
+        assert!(plain.get_estimated_bandwidth() > 300_000);
+        assert_eq!(bucketed.get_estimated_bandwidth(), 100_000);
+    }
+
+    fn ladder_from_bitrates(bitrates: &[u32]) -> Vec<QualityLevel> {
+        bitrates
+            .iter()
+            .map(|&bitrate| QualityLevel {
+                bitrate,
+                width: 1280,
+                height: 720,
+                codec: "h264".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ladder_gap_analysis() {
+        let gapped = AdaptiveBitrateStreamer::new(ladder_from_bitrates(&[500_000, 1_000_000, 5_000_000]));
+        let gaps = gapped.ladder_gap_analysis();
+        assert_eq!(gaps.len(), 1);
+        assert_eq!((gaps[0].lower_index, gaps[0].upper_index), (1, 2));
+        assert!((gaps[0].bitrate_ratio - 5.0).abs() < 1e-9);
+        
+        let mut smooth = AdaptiveBitrateStreamer::new(ladder_from_bitrates(&[1_000_000, 1_500_000, 2_500_000, 4_000_000]));
+        assert!(smooth.ladder_gap_analysis().is_empty());
+        
+        smooth.set_ladder_gap_threshold(1.55).unwrap();
+        assert_eq!(smooth.ladder_gap_analysis().len(), 2);
+        assert!(smooth.set_ladder_gap_threshold(1.0).is_err());
+    }
+
+    fn ladder_with_codecs(codecs: &[&str]) -> Vec<QualityLevel> {
+        create_test_quality_levels()
+            .into_iter()
+            .zip(codecs)
+            .map(|(quality, codec)| QualityLevel { codec: codec.to_string(), ..quality })
+            .collect()
+    }
+
+    #[test]
+    fn test_supported_codec_filtering() {
+        let h264_only: HashSet<String> = ["h264".to_string()].into_iter().collect();
+        
+        let mut av1_ladder = AdaptiveBitrateStreamer::new(ladder_with_codecs(&["av1"; 4]));
+        av1_ladder.set_supported_codecs(h264_only.clone());
+        for _ in 0..3 {
+            av1_ladder.record_segment_download(2_000_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        av1_ladder.buffer_state.current_level = Duration::from_secs(35);
+        assert!(!av1_ladder.has_feasible_quality());
+        assert_eq!(av1_ladder.find_feasible_quality(u32::MAX), None);
+        
+        // Mixed ladder: the av1 renditions are stepped over
+        let mut mixed = AdaptiveBitrateStreamer::new(ladder_with_codecs(&["h264", "h264", "av1", "av1"]));
+        mixed.set_supported_codecs(h264_only);
+        for _ in 0..3 {
+            mixed.record_segment_download(2_000_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        mixed.buffer_state.current_level = Duration::from_secs(35);
+        assert!(mixed.has_feasible_quality());
+        for _ in 0..3 {
+            assert_eq!(mixed.get_next_quality(), 1);
+        }
+    }
+
+    #[test]
+    fn test_per_level_upswitch_margin() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_utilization_layers(UtilizationLayers {
+            min_of_estimators: true,
+            buffer_factor: false,
+            safety_factor: false,
+        });
+        streamer.current_quality = 0;
+        streamer.set_upswitch_margin_for(2, 1.1).unwrap();
+        streamer.set_upswitch_margin_for(3, 1.5).unwrap();
+        assert_eq!(streamer.upswitch_margin_for(1), 1.0);
+        assert!(streamer.set_upswitch_margin_for(4, 1.2).is_err());
+        assert!(streamer.set_upswitch_margin(0.9).is_err());
+        
+        // 800KB/s covers 5 Mbps (625KB/s) but not the 1.5x headroom on top
+        for _ in 0..3 {
+            streamer.record_segment_download(3_200_000, Duration::from_secs(4), Duration::from_secs(4));
+        }
+        streamer.buffer_state.current_level = Duration::from_secs(30);
+        
+        let mut qualities: Vec<usize> = Vec::new();
+        for _ in 0..5 {
+            qualities.push(streamer.get_next_quality());
+        }
+        assert!(qualities.contains(&1));
+        assert_eq!(*qualities.last().unwrap(), 2);
+        assert!(!qualities.contains(&3));
+        assert_eq!(streamer.get_next_quality_detailed().target_quality, 3);
+    }
+
+    #[test]
+    fn test_step_matches_manual_sequence() {
+        let mut stepped = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        let mut manual = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        
+        // The last cycle drains more than is buffered, so it covers a rebuffer
+        let cycles: [(u32, u64, u64); 5] = [(500_000, 1, 3), (800_000, 1, 2), (600_000, 2, 4), (200_000, 4, 4), (300_000, 1, 20)];
+        for (size, download_secs, consumed_secs) in cycles {
+            let download: SegmentDownload = SegmentDownload {
+                segment_size: size,
+                download_duration: Duration::from_secs(download_secs),
+                segment_duration: Duration::from_secs(4),
+            };
+            let consumed: Duration = Duration::from_secs(consumed_secs);
+            
+            let stepped_decision: QualityDecision = stepped.step(download, consumed);
+            manual.record_segment_download(download.segment_size, download.download_duration, download.segment_duration);
+            manual.update_buffer_consumption(consumed);
+            let manual_decision: QualityDecision = manual.get_next_quality_detailed();
+            
+            assert_eq!(stepped_decision.quality, manual_decision.quality);
+            assert_eq!(stepped_decision.target_quality, manual_decision.target_quality);
+            assert_eq!(stepped_decision.estimated_bandwidth, manual_decision.estimated_bandwidth);
+            assert_eq!(stepped_decision.buffer_level, manual_decision.buffer_level);
+        }
+        
+        assert_eq!(stepped.current_quality, manual.current_quality);
+        assert_eq!(stepped.get_buffer_state().current_level, manual.get_buffer_state().current_level);
+        assert_eq!(stepped.rebuffer_count(), 1);
+        assert_eq!(stepped.rebuffer_count(), manual.rebuffer_count());
+        assert_eq!(stepped.rebuffer_time(), manual.rebuffer_time());
+        assert_eq!(stepped.total_segments(), manual.total_segments());
+    }
+
+    #[test]
+    fn test_zero_duration_policy() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_zero_duration_policy(ZeroDurationPolicy::Ignore);
+        for _ in 0..3 {
+            streamer.record_segment_download(400_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        let estimate: u32 = streamer.get_estimated_bandwidth();
+        
+        // A cache hit still fills the buffer but leaves the estimate alone
+        streamer.record_segment_download(400_000, Duration::from_secs(0), Duration::from_secs(4));
+        assert_eq!(streamer.get_estimated_bandwidth(), estimate);
+        assert_eq!(streamer.bandwidth_history.len(), 3);
+        assert_eq!(streamer.total_segments(), 4);
+        assert_eq!(streamer.get_buffer_state().current_level, Duration::from_secs(16));
+        
+        streamer.set_zero_duration_policy(ZeroDurationPolicy::Clamp);
+        streamer.record_segment_download(400_000, Duration::from_secs(0), Duration::from_secs(4));
+        assert_eq!(streamer.bandwidth_history.back().unwrap().1, 400_000_000);
+        
+        streamer.set_zero_duration_policy(ZeroDurationPolicy::MaxBandwidth);
+        streamer.record_segment_download(400_000, Duration::from_secs(0), Duration::from_secs(4));
+        assert_eq!(streamer.bandwidth_history.back().unwrap().1, u32::MAX);
+    }
+
+    #[test]
+    fn test_buffer_factor_curve() {
+        let streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        
+        // Panic below 3s, ramp 0.6 -> 1.0 up to the 30s target, 1.0 until the
+        // 45s seek threshold, then the seeking factor
+        assert_eq!(streamer.buffer_factor_at(Duration::from_secs(0)), 0.3);
+        assert_eq!(streamer.buffer_factor_at(Duration::from_millis(2_999)), 0.3);
+        assert!((streamer.buffer_factor_at(Duration::from_secs(3)) - 0.64).abs() < 1e-9);
+        assert!((streamer.buffer_factor_at(Duration::from_secs(15)) - 0.8).abs() < 1e-9);
+        assert_eq!(streamer.buffer_factor_at(Duration::from_secs(30)), 1.0);
+        assert_eq!(streamer.buffer_factor_at(Duration::from_secs(45)), 1.0);
+        assert_eq!(streamer.buffer_factor_at(Duration::from_secs(46)), SEEKING_BUFFER_FACTOR);
+        
+        let mut previous_factor: f64 = 0.0;
+        for secs in 0..=60 {
+            let factor: f64 = streamer.buffer_factor_at(Duration::from_secs(secs));
+            assert!(factor >= previous_factor);
+            previous_factor = factor;
+        }
+        assert_eq!(streamer.get_buffer_state().current_level, Duration::from_secs(0));
+    }
+
+    #[test]
+    fn test_post_rebuffer_discount() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_post_rebuffer_discount(0.5, 4).unwrap();
+        assert!(streamer.set_post_rebuffer_discount(0.0, 4).is_err());
+        
+        for _ in 0..3 {
+            streamer.record_segment_download(400_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        let full_estimate: f64 = streamer.get_estimated_bandwidth() as f64;
+        
+        streamer.update_buffer_consumption(Duration::from_secs(20));
+        assert_eq!(streamer.rebuffer_count(), 1);
+        assert!((streamer.get_estimated_bandwidth() as f64 - full_estimate * 0.5).abs() <= 1.0);
+        
+        // Recovers a quarter of the discount per segment
+        let mut previous_estimate: f64 = full_estimate * 0.5;
+        for _ in 0..3 {
+            streamer.record_segment_download(400_000, Duration::from_secs(1), Duration::from_secs(4));
+            let estimate: f64 = streamer.get_estimated_bandwidth() as f64;
+            assert!(estimate > previous_estimate && estimate < full_estimate);
+            previous_estimate = estimate;
+        }
+        streamer.record_segment_download(400_000, Duration::from_secs(1), Duration::from_secs(4));
+        assert!((streamer.get_estimated_bandwidth() as f64 - full_estimate).abs() <= 1.0);
+    }
+
+    #[test]
+    fn test_rate_conversions() {
+        for bitrate in [8, 800, 500_000, 2_500_000, 5_000_000] {
+            assert_eq!(byte_rate_to_bitrate(bitrate_to_byte_rate(bitrate)), bitrate);
+        }
+        for byte_rate in [0, 1, 1_000, 625_000] {
+            assert_eq!(bitrate_to_byte_rate(byte_rate_to_bitrate(byte_rate)), byte_rate);
+        }
+        
+        // Rounds to nearest rather than truncating
+        assert_eq!(bitrate_to_byte_rate(3), 0);
+        assert_eq!(bitrate_to_byte_rate(4), 1);
+        assert_eq!(bitrate_to_byte_rate(11), 1);
+        assert_eq!(bitrate_to_byte_rate(12), 2);
+        assert_eq!(bitrate_to_byte_rate(u32::MAX), 536_870_912);
+        assert_eq!(byte_rate_to_bitrate(u32::MAX), u32::MAX);
+    }
+
+    #[test]
+    fn test_fast_recovery_to_last_stable_quality() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        streamer.current_quality = 3;
+        
+        for _ in 0..3 {
+            streamer.record_segment_download(2_000_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        streamer.buffer_state.current_level = Duration::from_secs(30);
+        assert_eq!(streamer.get_next_quality(), 3);
+        clock.advance(Duration::from_secs(20));
+        
+        // A brief dip walks quality down one step at a time
+        for _ in 0..3 {
+            streamer.record_segment_download(100_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        streamer.buffer_state.current_level = Duration::from_secs(30);
+        for expected in [2, 1, 0] {
+            assert_eq!(streamer.get_next_quality(), expected);
+        }
+        assert_eq!(streamer.last_stable_quality(), Some(3));
+        
+        // Recovery jumps back in one decision instead of three single steps
+        clock.advance(Duration::from_secs(11));
+        for _ in 0..3 {
+            streamer.record_segment_download(2_000_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        streamer.buffer_state.current_level = Duration::from_secs(30);
+        assert_eq!(streamer.get_next_quality(), 3);
+        assert_eq!(streamer.last_stable_quality(), None);
+    }
+
+    #[test]
+    fn test_download_ratio() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        assert_eq!(streamer.download_ratio(), 0.0);
+        
+        for _ in 0..4 {
+            streamer.record_segment_download(500_000, Duration::from_secs(2), Duration::from_secs(4));
+        }
+        assert!((streamer.download_ratio() - 0.5).abs() < 1e-9);
+        
+        streamer.record_segment_download(500_000, Duration::from_secs(12), Duration::from_secs(4));
+        assert!((streamer.download_ratio() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_startup_safety_factor() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        streamer.set_startup_safety_factor(0.5).unwrap();
+        assert!(streamer.set_startup_safety_factor(1.5).is_err());
+        
+        // The Wifi seed matches the measured 625KB/s, so only confidence differs
+        streamer.set_network_class(NetworkClass::Wifi);
+        streamer.buffer_state.current_level = Duration::from_secs(30);
+        let cold_decision = streamer.get_next_quality_detailed();
+        let cold_utilization: f64 = streamer.effective_utilization();
+        
+        streamer.record_segment_download(2_500_000, Duration::from_secs(4), Duration::from_secs(4));
+        streamer.buffer_state.current_level = Duration::from_secs(30);
+        let warming_utilization: f64 = streamer.effective_utilization();
+        
+        streamer.record_segment_download(2_500_000, Duration::from_secs(4), Duration::from_secs(4));
+        streamer.buffer_state.current_level = Duration::from_secs(30);
+        assert!(streamer.has_confident_estimate());
+        let warm_decision = streamer.get_next_quality_detailed();
+        
+        assert!(cold_utilization < warming_utilization);
+        assert!((warming_utilization - streamer.effective_utilization()).abs() > 1e-9);
+        assert!((streamer.effective_utilization() - 0.8).abs() < 1e-6);
+        assert_eq!(cold_decision.estimated_bandwidth, warm_decision.estimated_bandwidth);
+        assert!(cold_decision.target_quality < warm_decision.target_quality);
+    }
+
+    #[test]
+    fn test_estimator_spread() {
+        let mut stable = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        assert_eq!(stable.estimator_spread(), 0.0);
+        for _ in 0..5 {
+            stable.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        assert!(stable.estimator_spread() < 0.01);
+        
+        let mut outlier = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        for _ in 0..4 {
+            outlier.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        outlier.record_segment_download(20_000, Duration::from_secs(1), Duration::from_secs(4));
+        assert!(outlier.estimator_spread() > 0.5);
+    }
+
+    #[test]
+    fn test_next_segment_size_hint() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        for _ in 0..3 {
+            streamer.record_segment_download(1_250_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        let unhinted: Duration = streamer.predicted_download_time(2);
+        
+        // Twice the size the bitrate implies doubles the prediction, at every level
+        streamer.set_next_segment_size_hint(Some(2_500_000));
+        let hinted: Duration = streamer.predicted_download_time(2);
+        assert!(hinted > unhinted);
+        assert!((hinted.as_secs_f64() - 2.0 * unhinted.as_secs_f64()).abs() < 0.01);
+        assert!(streamer.predicted_download_time(3) > hinted);
+        
+        streamer.get_next_quality();
+        assert_eq!(streamer.predicted_download_time(2), unhinted);
+    }
+
+    #[test]
+    fn test_selection_hysteresis() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_selection_hysteresis(0.1).unwrap();
+        assert!(streamer.set_selection_hysteresis(1.0).is_err());
+        streamer.buffer_state.current_level = Duration::from_secs(30);
+        
+        // 2.5 Mbps needs 312.5KB/s to stay but 343.75KB/s to step up into
+        streamer.current_quality = 1;
+        for bandwidth in [305_000, 325_000, 310_000, 340_000] {
+            assert_eq!(streamer.find_suitable_quality(bandwidth), 1);
+        }
+        assert_eq!(streamer.find_suitable_quality(345_000), 2);
+        
+        streamer.current_quality = 2;
+        for bandwidth in [315_000, 340_000, 313_000, 330_000] {
+            assert_eq!(streamer.find_suitable_quality(bandwidth), 2);
+        }
+        assert_eq!(streamer.find_suitable_quality(310_000), 1);
+    }
+
+    #[test]
+    fn test_upswitch_suppression_reasons() {
+        let no_layers: UtilizationLayers = UtilizationLayers {
+            min_of_estimators: true,
+            buffer_factor: false,
+            safety_factor: false,
+        };
+        
+        let mut margin = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        margin.set_utilization_layers(no_layers);
+        margin.set_upswitch_margin_for(3, 1.5).unwrap();
+        for _ in 0..3 {
+            margin.record_segment_download(3_200_000, Duration::from_secs(4), Duration::from_secs(4));
+        }
+        margin.buffer_state.current_level = Duration::from_secs(30);
+        let decision = margin.get_next_quality_detailed();
+        assert_eq!(decision.quality, 2);
+        assert_eq!(decision.upswitch_suppressed_by, Some(SuppressionReason::Margin));
+        
+        let mut safety = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        safety.set_utilization_layers(no_layers);
+        safety.set_upswitch_buffer_safety(true);
+        for _ in 0..3 {
+            safety.record_segment_download(7_000_000, Duration::from_secs(10), Duration::from_secs(10));
+        }
+        safety.buffer_state.current_level = Duration::from_secs(8);
+        assert_eq!(safety.get_next_quality_detailed().upswitch_suppressed_by, Some(SuppressionReason::BufferSafety));
+        
+        let clock = MockClock::new();
+        let mut deadline = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        deadline.set_clock(Box::new(clock.clone()));
+        for _ in 0..3 {
+            deadline.record_segment_download(2_000_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        deadline.buffer_state.current_level = Duration::from_secs(30);
+        deadline.set_next_segment_deadline(Some(clock.now() + Duration::from_secs(1)));
+        let decision = deadline.get_next_quality_detailed();
+        assert!(decision.quality < 2);
+        assert_eq!(decision.upswitch_suppressed_by, Some(SuppressionReason::Deadline));
+        
+        // Nothing held back once the gates are satisfied
+        deadline.set_next_segment_deadline(None);
+        assert_eq!(deadline.get_next_quality_detailed().upswitch_suppressed_by, None);
+        
+        // 2MB/s carries the top quality from 1 Mbps with a healthy buffer
+        let fast_at_one = |configure: &dyn Fn(&mut AdaptiveBitrateStreamer)| -> QualityDecision {
+            let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+            streamer.set_clock(Box::new(clock.clone()));
+            for _ in 0..3 {
+                streamer.record_segment_download(2_000_000, Duration::from_secs(1), Duration::from_secs(4));
+            }
+            streamer.current_quality = 1;
+            streamer.buffer_state.current_level = Duration::from_secs(30);
+            configure(&mut streamer);
+            streamer.get_next_quality_detailed()
+        };
+        
+        let decision = fast_at_one(&|streamer| streamer.set_min_upswitch_samples(5));
+        assert_eq!(decision.quality, 1);
+        assert_eq!(decision.upswitch_suppressed_by, Some(SuppressionReason::Transient));
+        
+        let decision = fast_at_one(&|streamer| {
+            streamer.set_max_switch_rate(Duration::from_secs(10));
+            streamer.last_switch_at = Some(clock.now());
+        });
+        assert_eq!(decision.quality, 1);
+        assert_eq!(decision.upswitch_suppressed_by, Some(SuppressionReason::RateLimit));
+        
+        // A 20MB segment can't arrive within its 4s of real time at any quality
+        let decision = fast_at_one(&|streamer| {
+            streamer.set_latency_mode(LatencyMode::LowLatency { target_latency: Duration::from_secs(30) });
+            streamer.next_segment_size_hint = Some((1, 20_000_000));
+        });
+        assert_eq!(decision.quality, 0);
+        assert_eq!(decision.upswitch_suppressed_by, Some(SuppressionReason::LiveEdge));
+        
+        // 4s is under min_level, so startup hasn't been established yet
+        let decision = fast_at_one(&|streamer| {
+            streamer.set_max_startup_quality(Some(1)).unwrap();
+            streamer.buffer_state.current_level = Duration::from_secs(4);
+        });
+        assert_eq!(decision.quality, 1);
+        assert_eq!(decision.upswitch_suppressed_by, Some(SuppressionReason::StartupCap));
+        
+        let decision = fast_at_one(&|streamer| streamer.set_viewport(1280, 720));
+        assert_eq!(decision.quality, 1);
+        assert_eq!(decision.upswitch_suppressed_by, Some(SuppressionReason::ResolutionCap));
+    }
+
+    #[test]
+    fn test_goodput_bandwidth_samples() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        
+        // 1.2MB on the wire, of which 1MB was segment payload
+        streamer.record_segment_download_goodput(1_200_000, 1_000_000, Duration::from_secs(2), Duration::from_secs(4));
+        assert_eq!(streamer.bandwidth_history.back().unwrap().1, 500_000);
+        assert_eq!(streamer.total_bytes(), 1_000_000);
+        assert_eq!(streamer.total_transferred_bytes(), 1_200_000);
+        
+        streamer.record_segment_download(1_000_000, Duration::from_secs(2), Duration::from_secs(4));
+        assert_eq!(streamer.total_transferred_bytes(), 2_200_000);
+    }
+
+    #[test]
+    fn test_history_tables() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        
+        for size in [300_000, 400_000, 500_000] {
+            streamer.record_segment_download(size, Duration::from_secs(1), Duration::from_secs(4));
+            clock.advance(Duration::from_secs(2));
+        }
+        
+        let bandwidth_table: Vec<(f64, u32)> = streamer.bandwidth_history_table();
+        assert_eq!(bandwidth_table, vec![(6.0, 300_000), (4.0, 400_000), (2.0, 500_000)]);
+        
+        let segment_table: Vec<(f64, usize, u32, f64, f64)> = streamer.segment_history_table();
+        assert_eq!(segment_table.len(), 3);
+        assert!(segment_table.windows(2).all(|rows| rows[0].0 > rows[1].0));
+        assert_eq!(segment_table[2], (2.0, 2, 500_000, 4.0, 1.0));
+    }
+
+    #[test]
+    fn test_boundary_policy_tolerance() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.buffer_state.current_level = Duration::from_secs(30);
+        
+        // 2.5 Mbps is 312.5KB/s, about 5% above a 297.6KB/s budget
+        let budget: u32 = 297_619;
+        assert_eq!(streamer.find_suitable_quality(budget), 1);
+        
+        streamer.set_boundary_policy(BoundaryPolicy::NearestWithinTolerance(0.1)).unwrap();
+        assert_eq!(streamer.find_suitable_quality(budget), 2);
+        assert_eq!(streamer.find_suitable_quality(280_000), 1);
+        assert!(streamer.set_boundary_policy(BoundaryPolicy::NearestWithinTolerance(-0.1)).is_err());
+    }
+
+    #[test]
+    fn test_seeded_rng_is_reproducible() {
+        let mut first = SeededRng::new(42);
+        let mut second = SeededRng::new(42);
+        let first_draws: Vec<f64> = (0..100).map(|_| first.next_f64()).collect();
+        let second_draws: Vec<f64> = (0..100).map(|_| second.next_f64()).collect();
+        assert_eq!(first_draws, second_draws);
+        assert!(first_draws.iter().all(|draw| (0.0..1.0).contains(draw)));
+        
+        let mut other_seed = SeededRng::new(7);
+        let other_draws: Vec<f64> = (0..100).map(|_| other_seed.next_f64()).collect();
+        assert_ne!(first_draws, other_draws);
+        
+        // Roughly uniform
+        let mean: f64 = first_draws.iter().sum::<f64>() / first_draws.len() as f64;
+        assert!((mean - 0.5).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_epsilon_greedy_exploration() {
+        let run = |mode: DecisionMode| -> Vec<usize> {
+            let clock = MockClock::new();
+            let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+            streamer.set_clock(Box::new(clock.clone()));
+            streamer.set_rng(Box::new(SeededRng::new(17)));
+            streamer.set_decision_mode(mode);
+            streamer.current_quality = 0;
+            
+            let mut qualities: Vec<usize> = Vec::new();
+            for size in [200_000, 220_000, 180_000, 210_000, 200_000] {
+                streamer.record_segment_download(size, Duration::from_secs(1), Duration::from_secs(4));
+                streamer.buffer_state.current_level = Duration::from_secs(30);
+                qualities.push(streamer.get_next_quality());
+                clock.advance(Duration::from_secs(4));
+            }
+            qualities
+        };
+        
+        let default_qualities: Vec<usize> = run(DecisionMode::Default);
+        assert_eq!(run(DecisionMode::EpsilonGreedy { epsilon: 0.0 }), default_qualities);
+        
+        // Each probe sits one level above where the previous decision put the
+        // regular path, never beyond the ladder
+        let probing: Vec<usize> = run(DecisionMode::EpsilonGreedy { epsilon: 1.0 });
+        assert_eq!(probing, run(DecisionMode::EpsilonGreedy { epsilon: 1.0 }));
+        assert!(probing.iter().zip(&default_qualities).all(|(probe, regular)| probe > regular || *probe == 3));
+        
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_decision_mode(DecisionMode::EpsilonGreedy { epsilon: 1.0 });
+        streamer.current_quality = 0;
+        for _ in 0..3 {
+            streamer.record_segment_download(60_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        streamer.buffer_state.current_level = Duration::from_secs(30);
+        assert_eq!(streamer.get_next_quality(), 1);
+        streamer.record_segment_download(500_000, Duration::from_secs(2), Duration::from_secs(4));
+        assert_eq!(streamer.exploration_stats(), (1, 1));
+        
+        // No probing on a thin buffer
+        streamer.buffer_state.current_level = Duration::from_secs(4);
+        streamer.get_next_quality_detailed();
+        assert_eq!(streamer.exploration_stats(), (1, 1));
+    }
+
+    #[test]
+    fn test_byte_buffer_units() {
+        let mut seconds = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        let mut bytes = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        bytes.set_buffer_unit(BufferUnit::Bytes);
+        
+        // At 1 Mbps one second of media is 125KB
+        for streamer in [&mut seconds, &mut bytes] {
+            streamer.current_quality = 1;
+            streamer.buffer_state.current_level = Duration::from_secs(10);
+        }
+        seconds.set_panic_threshold_in_units(4.0);
+        bytes.set_panic_threshold_in_units(500_000.0);
+        assert_eq!(bytes.buffer_level_in_units(), 1_250_000.0);
+        
+        seconds.update_buffer_consumption_in_units(6.5);
+        bytes.update_buffer_consumption_in_units(812_500.0);
+        assert_eq!(seconds.get_buffer_state().current_level, bytes.get_buffer_state().current_level);
+        assert_eq!(bytes.get_buffer_state().current_level, Duration::from_millis(3_500));
+        
+        // Both are inside the 4s panic zone
+        assert_eq!(seconds.calculate_buffer_factor(), seconds.panic_factor);
+        assert_eq!(bytes.calculate_buffer_factor(), bytes.panic_factor);
+        
+        bytes.update_buffer_consumption_in_units(1_000_000.0);
+        seconds.update_buffer_consumption_in_units(8.0);
+        assert_eq!(bytes.rebuffer_count(), 1);
+        assert_eq!(bytes.rebuffer_time(), seconds.rebuffer_time());
+    }
+
+    #[test]
+    fn test_expected_qoe_of_candidate() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        streamer.current_quality = 1;
+        for _ in 0..3 {
+            streamer.record_segment_download(600_000, Duration::from_secs(2), Duration::from_secs(4));
+        }
+        
+        // At 300KB/s: 2.5 Mbps takes ~4.2s per 4s segment and drains 2s of
+        // buffer over ~50s, 5 Mbps takes ~8.3s and runs dry after ~3.8s
+        streamer.buffer_state.current_level = Duration::from_secs(2);
+        let sustainable: f64 = streamer.expected_qoe_of(2);
+        assert!(sustainable > streamer.expected_qoe_of(1));
+        assert!(sustainable > streamer.expected_qoe_of(3));
+        assert_eq!(streamer.expected_qoe_of(4), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_peak_bandwidth_survives_window() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        assert_eq!(streamer.peak_bandwidth(), 0);
+        
+        streamer.record_segment_download(3_000_000, Duration::from_secs(1), Duration::from_secs(4));
+        clock.advance(Duration::from_secs(30));
+        streamer.record_segment_download(400_000, Duration::from_secs(1), Duration::from_secs(4));
+        assert_eq!(streamer.bandwidth_history.len(), 1);
+        assert_eq!(streamer.peak_bandwidth(), 3_000_000);
+        
+        streamer.reset_peak_bandwidth();
+        assert_eq!(streamer.peak_bandwidth(), 0);
+        streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+        assert_eq!(streamer.peak_bandwidth(), 500_000);
+    }
+
+    #[test]
+    fn test_concurrent_playback_during_download() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.buffer_state.current_level = Duration::from_secs(2);
+        
+        // A 6s download with playback running empties a 2s buffer mid-download
+        streamer.record_download_with_concurrent_playback(
+            300_000,
+            Duration::from_secs(6),
+            Duration::from_secs(4),
+            Duration::from_secs(6),
+        );
+        assert_eq!(streamer.rebuffer_count(), 1);
+        assert_eq!(streamer.rebuffer_time(), Duration::from_secs(4));
+        assert_eq!(streamer.get_buffer_state().current_level, Duration::from_secs(4));
+        
+        // A fast download nets the buffer without stalling
+        streamer.record_download_with_concurrent_playback(
+            300_000,
+            Duration::from_secs(1),
+            Duration::from_secs(4),
+            Duration::from_secs(1),
+        );
+        assert_eq!(streamer.rebuffer_count(), 1);
+        assert_eq!(streamer.get_buffer_state().current_level, Duration::from_secs(7));
+    }
+
+    #[test]
+    fn test_viewport_caps_selection() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        for _ in 0..3 {
+            streamer.record_segment_download(4_000_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        streamer.buffer_state.current_level = Duration::from_secs(30);
+        assert_eq!(streamer.find_suitable_quality(u32::MAX), 3);
+        
+        streamer.set_viewport(1280, 720);
+        assert_eq!(streamer.find_suitable_quality(u32::MAX), 1);
+        for _ in 0..4 {
+            assert!(streamer.get_next_quality() <= 1);
+        }
+        assert_eq!(streamer.get_next_quality(), 1);
+        
+        // A viewport larger than any rendition leaves the ladder uncapped
+        streamer.set_viewport(7680, 4320);
+        assert_eq!(streamer.find_suitable_quality(u32::MAX), 3);
+    }
+
+    #[test]
+    fn test_hybrid_mode_blends_targets() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        for _ in 0..3 {
+            streamer.record_segment_download(2_000_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        
+        // Throughput alone affords the top level; 10s of buffer maps to level 1
+        streamer.buffer_state.current_level = Duration::from_secs(10);
+        assert_eq!(streamer.throughput_target_quality(streamer.estimate_bandwidth()), 3);
+        assert_eq!(streamer.buffer_target_quality(), 1);
+        
+        let mut target_at = |weight: f64| -> usize {
+            streamer.set_decision_mode(DecisionMode::Hybrid { throughput_weight: weight });
+            streamer.get_next_quality_detailed().target_quality
+        };
+        assert_eq!(target_at(0.0), 1);
+        assert_eq!(target_at(1.0), 3);
+        assert_eq!(target_at(0.5), 2);
+    }
+
+    #[test]
+    fn test_session_id_and_reset() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        streamer.set_session_id("player-42".to_string());
+        assert_eq!(streamer.session_id(), "player-42");
+        
+        clock.advance(Duration::from_secs(90));
+        streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+        streamer.update_buffer_consumption(Duration::from_secs(10));
+        let metrics: AbrMetrics = streamer.metrics();
+        assert_eq!(metrics.session_id, "player-42");
+        assert_eq!(metrics.session_elapsed, Duration::from_secs(90));
+        
+        streamer.reset();
+        assert_ne!(streamer.session_id(), "player-42");
+        assert_eq!(streamer.session_start(), clock.now());
+        assert_eq!(streamer.session_elapsed(), Duration::from_secs(0));
+        assert_eq!(streamer.total_segments(), 0);
+        assert_eq!(streamer.rebuffer_count(), 0);
+        assert_eq!(streamer.current_quality, 2);
+        
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(streamer.metrics().session_elapsed, Duration::from_secs(5));
+        
+        let other = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        assert_ne!(other.session_id(), streamer.session_id());
+    }
+
+    #[test]
+    fn test_first_sample_exclusion() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        streamer.min_bandwidth_samples = 1;
+        streamer.set_first_sample_policy(FirstSamplePolicy::Exclude { decisions: 2 });
+        
+        // Slow start on the first segment, then the link opens up
+        streamer.record_segment_download(100_000, Duration::from_secs(1), Duration::from_secs(4));
+        streamer.get_next_quality();
+        clock.advance(Duration::from_secs(1));
+        streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+        assert!(streamer.get_estimated_bandwidth().abs_diff(500_000) <= 1);
+        
+        // Back in the estimate once the exclusion window has passed
+        streamer.get_next_quality();
+        assert!(streamer.get_estimated_bandwidth() < 400_000);
+        
+        streamer.set_first_sample_policy(FirstSamplePolicy::Include);
+        streamer.reset();
+        streamer.record_segment_download(100_000, Duration::from_secs(1), Duration::from_secs(4));
+        assert!(streamer.get_estimated_bandwidth().abs_diff(100_000) <= 1);
+    }
+
+    #[test]
+    fn test_quality_tier_distribution() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        for (quality, segments) in [(0, 2), (1, 3), (2, 1), (3, 4)] {
+            streamer.current_quality = quality;
+            for _ in 0..segments {
+                streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+            }
+        }
+        
+        let tiers: [ResolutionTier; 3] = [ResolutionTier::SD, ResolutionTier::HD, ResolutionTier::UHD];
+        let distribution: HashMap<ResolutionTier, Duration> = streamer.quality_tier_distribution(&tiers);
+        assert_eq!(distribution[&ResolutionTier::SD], Duration::from_secs(8));
+        assert_eq!(distribution[&ResolutionTier::HD], Duration::from_secs(16));
+        assert_eq!(distribution[&ResolutionTier::UHD], Duration::from_secs(16));
+        
+        // Without an SD tier the 360p time is not attributed anywhere
+        let upper: HashMap<ResolutionTier, Duration> =
+            streamer.quality_tier_distribution(&[ResolutionTier::HD, ResolutionTier::UHD]);
+        assert_eq!(upper.values().sum::<Duration>(), Duration::from_secs(32));
+    }
+
+    #[test]
+    fn test_multi_step_upswitch_on_full_buffer() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_max_upswitch_steps_when_full(2);
+        for _ in 0..3 {
+            streamer.record_segment_download(4_000_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        
+        streamer.current_quality = 0;
+        streamer.buffer_state.current_level = Duration::from_secs(50);
+        assert_eq!(streamer.get_next_quality(), 2);
+        
+        // Half full: still one step at a time
+        streamer.current_quality = 0;
+        streamer.buffer_state.current_level = Duration::from_secs(30);
+        assert_eq!(streamer.get_next_quality(), 1);
+    }
+
+    #[test]
+    fn test_time_since_last_switch() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(streamer.time_since_last_switch(), Duration::from_secs(3));
+        
+        streamer.commit_quality(3);
+        clock.advance(Duration::from_secs(7));
+        assert_eq!(streamer.time_since_last_switch(), Duration::from_secs(7));
+        
+        // Re-committing the same index is not a switch
+        streamer.commit_quality(3);
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(streamer.time_since_last_switch(), Duration::from_secs(9));
+        
+        streamer.commit_quality(1);
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(streamer.time_since_last_switch(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_size_weighted_estimate() {
+        let run = |size_weighted: bool| -> u32 {
+            let clock = MockClock::new();
+            let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+            streamer.set_clock(Box::new(clock.clone()));
+            streamer.set_utilization_layers(UtilizationLayers {
+                min_of_estimators: false,
+                buffer_factor: true,
+                safety_factor: true,
+            });
+            streamer.set_size_weighted_estimate(size_weighted);
+            
+            // One 4MB segment at 500KB/s among tiny segments reading 2MB/s
+            streamer.record_segment_download(4_000_000, Duration::from_secs(8), Duration::from_secs(4));
+            for _ in 0..4 {
+                clock.advance(Duration::from_millis(100));
+                streamer.record_segment_download(20_000, Duration::from_millis(10), Duration::from_secs(4));
+            }
+            streamer.get_estimated_bandwidth()
+        };
+        
+        assert!(run(false) > 1_500_000);
+        let weighted: u32 = run(true);
+        assert!(weighted < 600_000, "size-weighted estimate was {}", weighted);
+    }
+
+    #[test]
+    fn test_initial_bandwidth_estimate() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.min_bandwidth_samples = 0;
+        
+        // Without a configured guess an empty history only yields the floor
+        assert_eq!(streamer.get_estimated_bandwidth(), 1_000);
+        
+        streamer.set_initial_bandwidth_estimate(90_000).unwrap();
+        assert_eq!(streamer.get_estimated_bandwidth(), 90_000);
+        streamer.current_quality = 3;
+        assert_eq!(streamer.get_estimated_bandwidth(), 90_000);
+        assert!(streamer.set_initial_bandwidth_estimate(0).is_err());
+        
+        streamer.record_segment_download(400_000, Duration::from_secs(1), Duration::from_secs(4));
+        assert!(streamer.get_estimated_bandwidth().abs_diff(400_000) <= 1);
+    }
+
+    #[test]
+    fn test_recommended_fetch_quality() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        for _ in 0..3 {
+            streamer.record_segment_download(4_000_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        streamer.current_quality = 0;
+        
+        streamer.buffer_state.current_level = Duration::from_secs(40);
+        assert_eq!(streamer.recommended_fetch_quality(), 3);
+        assert_eq!(streamer.get_next_quality(), 1);
+        assert_eq!(streamer.recommended_fetch_quality(), 3);
+        
+        streamer.buffer_state.current_level = Duration::from_secs(10);
+        assert_eq!(streamer.recommended_fetch_quality(), 1);
+    }
+
+    struct NanQoe;
+
+    impl QoeModel for NanQoe {
+        fn score(&self, _session: &SessionStats) -> f64 {
+            f64::NAN
+        }
+    }
+
+    #[test]
+    fn test_degenerate_inputs_stay_finite() {
+        // Zero-length buffer thresholds
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.buffer_state.target_level = Duration::from_secs(0);
+        streamer.buffer_state.min_level = Duration::from_secs(0);
+        streamer.buffer_panic_threshold = Duration::from_secs(0);
+        for secs in [0, 1, 60] {
+            assert!(streamer.buffer_factor_at(Duration::from_secs(secs)).is_finite());
+        }
+        assert!(streamer.effective_utilization().is_finite());
+        
+        // Empty history with no sample requirement
+        let mut empty = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        empty.min_bandwidth_samples = 0;
+        assert_eq!(empty.get_estimated_bandwidth(), 1_000);
+        assert_eq!(empty.bandwidth_cv(), 0.0);
+        assert_eq!(empty.estimator_spread(), 0.0);
+        assert_eq!(empty.throughput_deficit(), 0.0);
+        assert_eq!(empty.get_next_quality(), 0);
+        
+        // All-zero throughput samples
+        let mut stalled = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        for _ in 0..3 {
+            stalled.record_segment_download(0, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        assert_eq!(stalled.get_estimated_bandwidth(), 1_000);
+        assert!(stalled.bandwidth_cv().is_finite());
+        assert!(stalled.estimator_spread().is_finite());
+        assert!(stalled.throughput_deficit().is_finite());
+        assert!(stalled.buffer_rate().is_finite());
+        assert!(stalled.download_ratio().is_finite());
+        
+        // A misbehaving QoE model ranks the candidate last instead of poisoning comparisons
+        stalled.set_qoe_model(Box::new(NanQoe));
+        assert_eq!(stalled.expected_qoe_of(1), f64::NEG_INFINITY);
+        
+        let quality: &QualityLevel = &create_test_quality_levels()[0];
+        assert_eq!(quality.bits_per_pixel(f32::NAN), 0.0);
+        assert_eq!(quality.bits_per_pixel(0.0), 0.0);
+    }
+
+    #[test]
+    fn test_adapt_only_after_min_buffer() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_adapt_only_after_min_buffer(true);
+        for _ in 0..3 {
+            streamer.record_segment_download(30_000, Duration::from_secs(1), Duration::from_secs(1));
+        }
+        
+        // Bandwidth calls for the lowest level, but the start is held
+        for level_ms in [0, 2_000, 4_900] {
+            streamer.buffer_state.current_level = Duration::from_millis(level_ms);
+            assert_eq!(streamer.get_next_quality(), 2);
+        }
+        
+        streamer.buffer_state.current_level = Duration::from_secs(5);
+        assert!(streamer.get_next_quality() < 2);
+        
+        // Stays adaptive after dipping back under min_level
+        streamer.buffer_state.current_level = Duration::from_secs(2);
+        assert_eq!(streamer.get_next_quality(), 0);
+    }
+
+    #[test]
+    fn test_max_switch_rate() {
+        fn run(max_switch_rate: Option<Duration>) -> (AdaptiveBitrateStreamer, u32, u32) {
+            let clock = MockClock::new();
+            let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+            streamer.set_clock(Box::new(clock.clone()));
+            if let Some(per) = max_switch_rate {
+                streamer.set_max_switch_rate(per);
+            }
+            streamer.min_bandwidth_samples = 1;
+            streamer.set_bandwidth_window(Duration::from_millis(400)).unwrap();
+            
+            // Half-second segments whose throughput swings every segment
+            let mut switches: u32 = 0;
+            let mut rate_limited: u32 = 0;
+            for i in 0..24 {
+                let size: u32 = if i % 2 == 0 { 2_000_000 } else { 20_000 };
+                streamer.record_segment_download(size, Duration::from_millis(500), Duration::from_millis(500));
+                streamer.buffer_state.current_level = Duration::from_secs(30);
+                let decision = streamer.get_next_quality_detailed();
+                if decision.quality != decision.previous_quality {
+                    switches += 1;
+                }
+                if decision.upswitch_suppressed_by == Some(SuppressionReason::RateLimit) {
+                    assert_eq!(decision.quality, decision.previous_quality);
+                    rate_limited += 1;
+                }
+                clock.advance(Duration::from_millis(500));
+            }
+            (streamer, switches, rate_limited)
+        }
+        
+        // Unlimited, every decision in the 12s follows the swing; capped, one
+        // switch lands at 0s, 4s and 8s
+        let (_, unlimited_switches, unlimited_rate_limited) = run(None);
+        let (mut streamer, switches, rate_limited) = run(Some(Duration::from_secs(4)));
+        assert_eq!(unlimited_switches, 24);
+        assert_eq!(unlimited_rate_limited, 0);
+        assert_eq!(switches, 3);
+        // Once the cap has walked it down, each held-back step up on a fast
+        // segment reports the rate limit
+        assert_eq!(rate_limited, 7);
+        
+        // A panic downgrade goes through right after a switch
+        streamer.commit_quality(3);
+        streamer.record_segment_download(20_000, Duration::from_millis(500), Duration::from_millis(500));
+        streamer.buffer_state.current_level = Duration::from_secs(2);
+        assert!(streamer.get_next_quality() < 3);
+    }
+
+    #[test]
+    fn test_buffer_deficit_and_surplus() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.buffer_state.current_level = Duration::from_secs(12);
+        assert_eq!(streamer.buffer_deficit(), Duration::from_secs(18));
+        assert_eq!(streamer.buffer_surplus(), Duration::from_secs(0));
+        
+        streamer.buffer_state.current_level = Duration::from_secs(41);
+        assert_eq!(streamer.buffer_deficit(), Duration::from_secs(0));
+        assert_eq!(streamer.buffer_surplus(), Duration::from_secs(11));
+    }
+
+    #[test]
+    fn test_complexity_inflates_predicted_size() {
+        let run = |recent_complexity: f32| -> (u32, usize) {
+            let clock = MockClock::new();
+            let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+            streamer.set_clock(Box::new(clock.clone()));
+            for i in 0..13 {
+                let complexity: f32 = if i >= 10 { recent_complexity } else { 1.0 };
+                streamer.record_segment_download_with_complexity(1_250_000, Duration::from_secs(1), Duration::from_secs(4), complexity);
+            }
+            streamer.buffer_state.current_level = Duration::from_secs(30);
+            let predicted_size: u32 = streamer.estimated_segment_size(2);
+            
+            streamer.set_next_segment_deadline(Some(clock.now() + Duration::from_millis(1_800)));
+            (predicted_size, streamer.get_next_quality())
+        };
+        
+        let (steady_size, steady_quality) = run(1.0);
+        let (complex_size, complex_quality) = run(2.0);
+        assert_eq!(steady_size, 1_250_000);
+        assert!(complex_size > steady_size);
+        assert!(complex_quality < steady_quality);
+    }
+
+    #[test]
+    fn test_steady_state_estimator() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        streamer.set_steady_state_estimator(true);
+        
+        let record = |streamer: &mut AdaptiveBitrateStreamer, i: u32| {
+            let size: u32 = if i.is_multiple_of(2) { 480_000 } else { 520_000 };
+            streamer.record_segment_download(size, Duration::from_secs(1), Duration::from_secs(4));
+            clock.advance(Duration::from_secs(1));
+        };
+        
+        for i in 0..3 {
+            record(&mut streamer, i);
+        }
+        assert!(!streamer.in_steady_state());
+        let pessimistic: u32 = streamer.get_estimated_bandwidth();
+        assert!(pessimistic <= 480_000);
+        
+        for i in 3..8 {
+            record(&mut streamer, i);
         }
+        assert!(streamer.in_steady_state());
+        let steady: u32 = streamer.get_estimated_bandwidth();
+        assert!(steady > pessimistic);
+        assert!(steady.abs_diff(500_000) < 15_000);
+        
+        // A volatile sample drops straight back to the pessimistic estimator
+        streamer.record_segment_download(50_000, Duration::from_secs(1), Duration::from_secs(4));
+        assert!(!streamer.in_steady_state());
     }
 
-    pub fn get_current_quality(&self) -> &QualityLevel {
-        &self.quality_levels[self.current_quality]
+    #[test]
+    fn test_decay_kernels() {
+        let estimate_with = |kernel: DecayKernel| -> u32 {
+            let clock = MockClock::new();
+            let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+            streamer.set_clock(Box::new(clock.clone()));
+            streamer.set_utilization_layers(UtilizationLayers {
+                min_of_estimators: false,
+                buffer_factor: true,
+                safety_factor: true,
+            });
+            streamer.min_bandwidth_samples = 1; // doubled to 2 for this volatile pair
+            streamer.set_decay_kernel(kernel);
+            
+            streamer.record_segment_download(100_000, Duration::from_secs(1), Duration::from_secs(4));
+            clock.advance(Duration::from_secs(8));
+            streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+            streamer.get_estimated_bandwidth()
+        };
+        
+        // Linear discounts an 8s-old sample hardest, no decay treats it as current
+        let exponential: u32 = estimate_with(DecayKernel::Exponential);
+        let linear: u32 = estimate_with(DecayKernel::Linear);
+        let flat: u32 = estimate_with(DecayKernel::None);
+        assert!(linear > exponential && exponential > flat);
+        assert!(flat.abs_diff(300_000) <= 1);
+        assert!(linear.abs_diff(433_333) <= 1);
     }
 
-    pub fn get_buffer_state(&self) -> &BufferState {
-        &self.buffer_state
+    #[test]
+    fn test_run_trace_golden() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        
+        let secs = Duration::from_secs;
+        let trace: [(u32, Duration, Duration, Duration); 8] = [
+            (1_250_000, secs(1), secs(4), secs(1)),
+            (1_250_000, secs(1), secs(4), secs(1)),
+            (1_250_000, secs(1), secs(4), secs(1)),
+            (2_500_000, secs(1), secs(4), secs(1)),
+            (2_500_000, secs(1), secs(4), secs(1)),
+            (500_000, secs(2), secs(4), secs(4)),
+            (200_000, secs(2), secs(4), secs(12)),
+            (200_000, secs(2), secs(4), secs(8)),
+        ];
+        let decisions: Vec<QualityDecision> = streamer.run_trace(&trace);
+        let qualities: Vec<usize> = decisions.iter().map(|decision| decision.quality).collect();
+        let buffer_secs: Vec<u64> = decisions.iter().map(|decision| decision.buffer_level.as_secs()).collect();
+        
+        assert_eq!(qualities, vec![1, 2, 3, 3, 3, 2, 1, 0]);
+        assert_eq!(buffer_secs, vec![3, 6, 9, 12, 15, 15, 7, 3]);
     }
 
-    pub fn get_estimated_bandwidth(&self) -> u32 {
-        self.estimate_bandwidth()
+    #[test]
+    fn test_buffer_consumption_reports_deficit() {
+        let mut streamer = AdaptiveBitrateStreamer::for_test(
+            create_test_quality_levels(),
+            2,
+            buffer_at(Duration::from_secs(2)),
+            StreamerConfig::default(),
+        );
+        
+        let deficit: Duration = streamer.update_buffer_consumption(Duration::from_secs(5));
+        assert_eq!(deficit, Duration::from_secs(3));
+        assert_eq!(streamer.get_buffer_state().current_level, Duration::ZERO);
+        assert_eq!(streamer.rebuffer_time(), deficit);
+        
+        streamer.buffer_state.current_level = Duration::from_secs(4);
+        assert_eq!(streamer.update_buffer_consumption(Duration::from_secs(1)), Duration::ZERO);
     }
 
-    pub fn is_buffer_healthy(&self) -> bool {
-        self.buffer_state.current_level >= self.buffer_state.min_level
+    #[test]
+    fn test_max_startup_quality() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        streamer.set_max_startup_quality(Some(1)).unwrap();
+        assert!(streamer.set_max_startup_quality(Some(4)).is_err());
+        
+        // Plenty of bandwidth but a thin buffer: the cap holds
+        for _ in 0..3 {
+            streamer.record_segment_download(2_500_000, Duration::from_secs(1), Duration::from_secs(1));
+        }
+        assert!(streamer.has_confident_estimate());
+        let decision: QualityDecision = streamer.get_next_quality_detailed();
+        assert_eq!(decision.quality, 1);
+        
+        // From there, stepping back up is what the cap holds back
+        let decision: QualityDecision = streamer.get_next_quality_detailed();
+        assert_eq!(decision.quality, 1);
+        assert!(decision.target_quality > 1);
+        assert_eq!(decision.upswitch_suppressed_by, Some(SuppressionReason::StartupCap));
+        
+        // Once the buffer reaches min_level the cap is gone for the session
+        streamer.buffer_state.current_level = Duration::from_secs(20);
+        assert!(streamer.get_next_quality() > 1);
+        streamer.buffer_state.current_level = Duration::from_secs(1);
+        assert_eq!(streamer.startup_quality_cap(), None);
     }
 
-    pub fn should_pause_playback(&self) -> bool {
-        self.buffer_state.current_level < Duration::from_secs(1)
+    #[test]
+    fn test_revalidate_current_quality() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        for _ in 0..3 {
+            streamer.record_segment_download(1_000_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        streamer.current_quality = 3;
+        streamer.buffer_state.current_level = Duration::from_secs(50);
+        assert_eq!(streamer.revalidate_current_quality(), None);
+        
+        // A stalled download drained the buffer behind the player's back
+        streamer.buffer_state.current_level = Duration::from_secs(2);
+        let downgraded: Option<usize> = streamer.revalidate_current_quality();
+        assert!(downgraded.is_some_and(|quality| quality < 3));
+        assert_eq!(Some(streamer.current_quality), downgraded);
+        assert_eq!(streamer.revalidate_current_quality(), None);
     }
-}
 
-fn create_test_quality_levels() -> Vec<QualityLevel> {
-    vec![
-        QualityLevel {
-            bitrate: 500_000,   // 500 kbps
-            width: 640,
-            height: 360,
-            codec: "h264".to_string(),
-        },
-        QualityLevel {
-            bitrate: 1_000_000, // 1 Mbps
-            width: 1280,
-            height: 720,
-            codec: "h264".to_string(),
-        },
-        QualityLevel {
-            bitrate: 2_500_000, // 2.5 Mbps
-            width: 1920,
-            height: 1080,
-            codec: "h264".to_string(),
-        },
-        QualityLevel {
-            bitrate: 5_000_000, // 5 Mbps
-            width: 3840,
-            height: 2160,
-            codec: "h264".to_string(),
-        },
-    ]
-}
+    #[test]
+    fn test_bandwidth_ceiling() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_bandwidth_ceiling(Some(200_000)).unwrap();
+        assert!(streamer.set_bandwidth_ceiling(Some(0)).is_err());
+        streamer.buffer_state.current_level = Duration::from_secs(30);
+        
+        for _ in 0..3 {
+            streamer.record_segment_download(2_500_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        assert_eq!(streamer.get_estimated_bandwidth(), 200_000);
+        assert!(streamer.get_next_quality() <= 1);
+        
+        streamer.set_bandwidth_ceiling(None).unwrap();
+        assert!(streamer.get_estimated_bandwidth() > 2_000_000);
+    }
 
-fn main() {
-    println!("Adaptive Bitrate Streaming Algorithm Demo");
-    
-    let quality_levels: Vec<QualityLevel> = create_test_quality_levels();
-    let mut streamer: AdaptiveBitrateStreamer = AdaptiveBitrateStreamer::new(quality_levels);
-    
-    println!("Initial quality: {} ({}x{} @ {} kbps)", 
-        streamer.current_quality,
-        streamer.get_current_quality().width,
-        streamer.get_current_quality().height,
-        streamer.get_current_quality().bitrate / 1000
-    );
-    
+    #[test]
+    fn test_expected_rebuffer_for() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        for _ in 0..3 {
+            streamer.record_segment_download(200_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        streamer.buffer_state.current_level = Duration::from_secs(10);
+        
+        // 5 Mbps segments take 12.5s each against 4s of playback gained per segment
+        assert!(streamer.expected_rebuffer_for(3, 4) > Duration::ZERO);
+        assert!(streamer.expected_rebuffer_for(3, 4) > streamer.expected_rebuffer_for(3, 1));
+        assert_eq!(streamer.expected_rebuffer_for(0, 4), Duration::ZERO);
+        assert_eq!(streamer.expected_rebuffer_for(3, 0), Duration::ZERO);
+        assert_eq!(streamer.expected_rebuffer_for(4, 4), Duration::MAX);
+    }
 
-    println!("\nSimulating segment downloads...");
-    
-    streamer.record_segment_download(
-        1_000_000, // 1MB segment
-        Duration::from_millis(800), // Downloaded in 800ms
-        Duration::from_secs(4), // 4-second segment
-    );
-    
-    // strong network
-    let next_quality = streamer.get_next_quality();
-    println!("After fast download - Next quality: {} (estimated bandwidth: {} kbps)", 
-        next_quality,
-        streamer.get_estimated_bandwidth() * 8 / 1000
-    );
-    
-    // poor network
-    streamer.record_segment_download(
-        500_000, // 500KB segment
-        Duration::from_secs(3), // Downloaded in 3 seconds
-        Duration::from_secs(4), // 4-second segment
-    );
-    
-    let next_quality = streamer.get_next_quality();
-    println!("After slow download - Next quality: {} (estimated bandwidth: {} kbps)", 
-        next_quality,
-        streamer.get_estimated_bandwidth() * 8 / 1000
-    );
-    
-    let buffer = streamer.get_buffer_state();
-    println!("\nBuffer state:");
-    println!("  Current level: {:.1}s", buffer.current_level.as_secs_f64());
-    println!("  Target level: {:.1}s", buffer.target_level.as_secs_f64());
-    println!("  Buffer healthy: {}", streamer.is_buffer_healthy());
-}
+    #[test]
+    fn test_buffer_factor_at_thresholds() {
+        let streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        let panic_threshold: Duration = streamer.buffer_panic_threshold;
+        let target: Duration = streamer.get_buffer_state().target_level;
+        let seek_threshold: Duration = streamer.seek_threshold();
+        let just_below = |level: Duration| level - Duration::from_millis(1);
+        
+        // Exactly at the panic threshold is already on the ramp
+        assert!((streamer.buffer_factor_at(panic_threshold) - 0.64).abs() < 1e-9);
+        assert_eq!(streamer.buffer_factor_at(just_below(panic_threshold)), streamer.panic_factor);
+        
+        // The ramp runs into the normal band without a step
+        assert_eq!(streamer.buffer_factor_at(target), 1.0);
+        assert!((streamer.buffer_factor_at(just_below(target)) - 1.0).abs() < 1e-4);
+        
+        // Exactly at the seek threshold is still normal
+        assert_eq!(streamer.buffer_factor_at(seek_threshold), 1.0);
+        assert_eq!(streamer.buffer_factor_at(seek_threshold + Duration::from_millis(1)), SEEKING_BUFFER_FACTOR);
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_bitrate_smoothness() {
+        let mut steady = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        let mut see_saw = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        assert_eq!(steady.bitrate_smoothness(), 0.0);
+        
+        for i in 0..8 {
+            steady.current_quality = 2;
+            see_saw.current_quality = if i % 2 == 0 { 0 } else { 3 };
+            for streamer in [&mut steady, &mut see_saw] {
+                streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+            }
+        }
+        
+        // Alternating 0.5 and 5 Mbps: 4.5 Mbps swings around a 2.75 Mbps mean
+        assert_eq!(steady.bitrate_smoothness(), 0.0);
+        assert!((see_saw.bitrate_smoothness() - 4.5 / 2.75).abs() < 1e-9);
+    }
 
     #[test]
-    fn test_initial_quality_selection() {
-        let quality_levels = create_test_quality_levels();
-        let streamer = AdaptiveBitrateStreamer::new(quality_levels);
+    fn test_min_upswitch_samples() {
+        let run = |min_samples: usize| -> Vec<usize> {
+            let clock = MockClock::new();
+            let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+            streamer.set_clock(Box::new(clock.clone()));
+            streamer.set_utilization_layers(UtilizationLayers {
+                min_of_estimators: false,
+                buffer_factor: true,
+                safety_factor: true,
+            });
+            streamer.set_min_upswitch_samples(min_samples);
+            streamer.min_bandwidth_samples = 1; // stay confident through the volatile jump
+            for _ in 0..3 {
+                streamer.record_segment_download(200_000, Duration::from_secs(1), Duration::from_secs(4));
+            }
+            streamer.current_quality = 1;
+            
+            [2_000_000, 2_000_000, 2_000_000]
+                .iter()
+                .map(|&size| {
+                    streamer.record_segment_download(size, Duration::from_secs(1), Duration::from_secs(4));
+                    streamer.buffer_state.current_level = Duration::from_secs(30);
+                    let decision: QualityDecision = streamer.get_next_quality_detailed();
+                    if min_samples > 0 && decision.quality == 1 {
+                        assert_eq!(decision.upswitch_suppressed_by, Some(SuppressionReason::Transient));
+                    }
+                    streamer.current_quality = 1;
+                    decision.quality
+                })
+                .collect()
+        };
         
-        // Should start with middle quality (index 2 for 4 levels)
-        assert_eq!(streamer.current_quality, 2);
+        // Without the requirement the first spike already steps up
+        assert_eq!(run(0), vec![2, 2, 2]);
+        // With it, only the third consecutive high sample unlocks the switch
+        assert_eq!(run(3), vec![1, 1, 2]);
     }
 
     #[test]
-    fn test_bandwidth_recording() {
+    fn test_conservative_quality() {
         let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.buffer_state.current_level = Duration::from_secs(30);
+        for size in [2_000_000, 1_500_000, 150_000, 2_500_000, 1_800_000] {
+            streamer.record_segment_download(size, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        streamer.buffer_state.current_level = Duration::from_secs(30);
         
-        streamer.record_segment_download(
-            1_000_000, // 1MB segment
-            Duration::from_secs(1), // Downloaded in 1 second
-            Duration::from_secs(4), // 4-second segment
+        // Under the safety factor the 150KB/s dip falls just short of 1 Mbps
+        let conservative: usize = streamer.conservative_quality();
+        assert_eq!(conservative, 0);
+        let decision: QualityDecision = streamer.get_next_quality_detailed();
+        assert!(conservative <= decision.target_quality);
+        assert!(conservative <= decision.quality);
+    }
+
+    #[test]
+    fn test_buffer_zone_transitions() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        let transitions: Rc<RefCell<Vec<(BufferZone, BufferZone)>>> = Rc::new(RefCell::new(Vec::new()));
+        let recorded = Rc::clone(&transitions);
+        streamer.on_buffer_state_change(Box::new(move |old, new| recorded.borrow_mut().push((old, new))));
+        
+        // 4s segments: 4s below target, 32s normal, 48s seeking
+        for _ in 0..12 {
+            streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        streamer.update_buffer_consumption(Duration::from_secs(10));
+        streamer.update_buffer_consumption(Duration::from_secs(37));
+        streamer.update_buffer_consumption(Duration::from_secs(5));
+        
+        assert_eq!(
+            *transitions.borrow(),
+            vec![
+                (BufferZone::Panic, BufferZone::BelowTarget),
+                (BufferZone::BelowTarget, BufferZone::Normal),
+                (BufferZone::Normal, BufferZone::Seeking),
+                (BufferZone::Seeking, BufferZone::Normal),
+                (BufferZone::Normal, BufferZone::Panic),
+            ]
         );
+        assert_eq!(streamer.buffer_zone(), BufferZone::Panic);
+    }
+
+    #[test]
+    fn test_selection_preference() {
+        let mut levels: Vec<QualityLevel> = create_test_quality_levels();
+        // Same 5 Mbps: 1080p against a high-fps 720p, listed after it
+        levels[3].width = 1920;
+        levels[3].height = 1080;
+        levels.push(QualityLevel { bitrate: 5_000_000, width: 1280, height: 720, codec: "h264".to_string() });
+        let mut streamer = AdaptiveBitrateStreamer::new(levels);
+        streamer.current_quality = 4;
         
-        assert_eq!(streamer.bandwidth_history.len(), 1);
-        assert_eq!(streamer.segment_history.len(), 1);
+        assert_eq!(streamer.find_suitable_quality(1_000_000), 4);
+        streamer.set_selection_preference(SelectionPreference::Resolution);
+        assert_eq!(streamer.find_suitable_quality(1_000_000), 3);
+        
+        // Below the 5 Mbps pair resolution rises with bitrate, so both agree
+        assert_eq!(streamer.find_suitable_quality(400_000), 2);
+    }
+
+    #[cfg(feature = "logging")]
+    #[test]
+    fn test_decision_emits_debug_record() {
+        // Records are kept per thread so parallel tests don't see each other's
+        struct CapturingLogger;
+        thread_local! {
+            static RECORDS: RefCell<Vec<(log::Level, String)>> = const { RefCell::new(Vec::new()) };
+        }
+        impl log::Log for CapturingLogger {
+            fn enabled(&self, _metadata: &log::Metadata) -> bool {
+                true
+            }
+            fn log(&self, record: &log::Record) {
+                RECORDS.with(|records| records.borrow_mut().push((record.level(), record.args().to_string())));
+            }
+            fn flush(&self) {}
+        }
+        static LOGGER: CapturingLogger = CapturingLogger;
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Trace);
+        
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        for _ in 0..3 {
+            streamer.record_segment_download(100_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        let quality: usize = streamer.get_next_quality();
+        
+        let records: Vec<(log::Level, String)> = RECORDS.with(|records| records.borrow().clone());
+        let expected: String = format!("decision quality={quality} previous=2");
+        assert!(records.iter().any(|(level, message)| *level == log::Level::Debug && message.starts_with(&expected)));
+        assert!(records.iter().any(|(level, message)| *level == log::Level::Trace && message.starts_with("bandwidth estimate")));
+    }
+
+    #[test]
+    fn test_pause_adaptation() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        for _ in 0..3 {
+            streamer.record_segment_download(2_500_000, Duration::from_secs(1), Duration::from_secs(4));
+        }
+        streamer.current_quality = 3;
+        streamer.buffer_state.current_level = Duration::from_secs(30);
+        
+        streamer.pause_adaptation();
+        for _ in 0..5 {
+            clock.advance(Duration::from_secs(4));
+            streamer.record_segment_download(50_000, Duration::from_secs(1), Duration::from_secs(4));
+            streamer.update_buffer_consumption(Duration::from_secs(4));
+            let decision: QualityDecision = streamer.get_next_quality_detailed();
+            assert_eq!(decision.quality, 3);
+            assert!(decision.target_quality < 3);
+        }
+        assert_eq!(streamer.total_segments(), 8);
+        
+        // The live edge is a hard limit, so it still pulls quality down while
+        // paused once a full-size segment can't arrive in real time
+        streamer.set_latency_mode(LatencyMode::LowLatency { target_latency: Duration::from_secs(6) });
+        streamer.set_next_segment_size_hint(Some(2_500_000));
+        let decision: QualityDecision = streamer.get_next_quality_detailed();
+        assert!(decision.quality < 3);
+        assert!(streamer.is_adaptation_paused());
+        streamer.set_latency_mode(LatencyMode::Normal);
+        streamer.commit_quality(3);
+        
+        // The slow downloads were learned while paused and count right away
+        streamer.resume_adaptation();
+        assert!(!streamer.is_adaptation_paused());
+        let decision: QualityDecision = streamer.get_next_quality_detailed();
+        assert!(decision.quality < 3);
+        assert!(decision.estimated_bandwidth < 200_000);
+    }
+
+    #[test]
+    fn test_buffer_holding_cost() {
+        let empty = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        assert_eq!(empty.buffer_holding_cost(), 0);
+        
+        // 10s at 2.5 Mbps
+        let mut streamer = AdaptiveBitrateStreamer::for_test(
+            create_test_quality_levels(),
+            2,
+            buffer_at(Duration::from_secs(10)),
+            StreamerConfig::default(),
+        );
+        assert_eq!(streamer.buffer_holding_cost(), 3_125_000);
+        streamer.buffer_state.current_level = Duration::from_secs(20);
+        assert_eq!(streamer.buffer_holding_cost(), 6_250_000);
+        streamer.current_quality = 3;
+        assert_eq!(streamer.buffer_holding_cost(), 12_500_000);
+        
+        // Same byte rate as everywhere else: 1_000_001 bps rounds to 125_000 B/s
+        streamer.quality_levels[3].bitrate = 1_000_001;
+        assert_eq!(streamer.buffer_holding_cost(), 2_500_000);
+    }
+
+    #[test]
+    fn test_panic_bandwidth_floor() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_panic_bandwidth_floor(true);
+        for _ in 0..3 {
+            streamer.record_segment_download(2_000, Duration::from_secs(2), Duration::from_secs(4));
+        }
+        streamer.buffer_state.current_level = Duration::from_millis(500);
+        streamer.current_quality = 1;
+        
+        // 1 KB/s under the panic factor would leave a budget of a few hundred bytes
+        let decision: QualityDecision = streamer.get_next_quality_detailed();
+        assert_eq!(decision.quality, 0);
+        assert_eq!(decision.effective_bandwidth, 62_500);
+        
+        streamer.set_panic_bandwidth_floor(false);
+        assert!(streamer.get_next_quality_detailed().effective_bandwidth < 1_000);
+    }
+
+    #[test]
+    fn test_replay_with() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        let mut live: Vec<usize> = Vec::new();
+        for _ in 0..8 {
+            clock.advance(Duration::from_secs(4));
+            // 3.2 Mbps throughput, whatever rendition was fetched
+            let size: u32 = streamer.get_current_quality().bitrate / 2;
+            let download: SegmentDownload = SegmentDownload {
+                segment_size: size,
+                download_duration: Duration::from_millis(1250 * size as u64 / 500_000),
+                segment_duration: Duration::from_secs(4),
+            };
+            live.push(streamer.step(download, Duration::from_secs(2)).quality);
+        }
+        let segments_before: usize = streamer.total_segments();
+        
+        // The live configuration reproduces the session
+        let same: Vec<usize> = streamer.replay_with(streamer.config()).unwrap().iter().map(|d| d.quality).collect();
+        assert_eq!(same, live);
+        
+        // Without safety margins 3.2 Mbps carries 2.5 Mbps instead of 1 Mbps
+        let aggressive: StreamerConfig = StreamerConfig {
+            safety_factor: 1.0,
+            startup_safety_factor: 1.0,
+            utilization_layers: UtilizationLayers { buffer_factor: false, ..UtilizationLayers::default() },
+            ..StreamerConfig::default()
+        };
+        let replayed: Vec<usize> = streamer.replay_with(aggressive).unwrap().iter().map(|d| d.quality).collect();
+        assert_eq!(replayed.len(), live.len());
+        assert!(replayed.iter().sum::<usize>() > live.iter().sum::<usize>());
+        assert_eq!(replayed.last(), Some(&2));
+        
+        assert_eq!(streamer.total_segments(), segments_before);
+        let unsafe_factor: StreamerConfig = StreamerConfig { safety_factor: 0.0, ..StreamerConfig::default() };
+        assert!(unsafe_factor.validate().is_err());
+        assert_eq!(
+            streamer.replay_with(unsafe_factor),
+            Err(ConfigError::OutOfRange { parameter: "safety_factor", value: 0.0 })
+        );
+        
+        // A config rejected part-way through is not half applied
+        let invalid: StreamerConfig = StreamerConfig { panic_factor: 0.5, selection_hysteresis: 2.0, ..StreamerConfig::default() };
+        assert!(streamer.apply_config(&invalid).is_err());
+        assert_eq!(streamer.config(), StreamerConfig::default());
+    }
+
+    #[test]
+    fn test_compare_configs() {
+        let secs = Duration::from_secs;
+        // A fast link that collapses for a stretch, then recovers
+        let trace: Vec<TraceEvent> = [800_000; 10]
+            .into_iter()
+            .chain([90_000; 6])
+            .chain([800_000; 4])
+            .map(|bandwidth| TraceEvent { bandwidth, segment_duration: secs(4) })
+            .collect();
+        let conservative: StreamerConfig = StreamerConfig { safety_factor: 0.5, ..StreamerConfig::default() };
+        let aggressive: StreamerConfig = StreamerConfig {
+            safety_factor: 1.0,
+            startup_safety_factor: 1.0,
+            utilization_layers: UtilizationLayers { buffer_factor: false, ..UtilizationLayers::default() },
+            ..StreamerConfig::default()
+        };
+        
+        let reports: Vec<SimulationReport> =
+            compare_configs(&create_test_quality_levels(), &trace, &[conservative.clone(), aggressive.clone()]).unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].config, conservative);
+        assert!(reports.iter().all(|report| report.decisions.len() == trace.len()));
+        assert!(reports[1].average_bitrate > reports[0].average_bitrate);
+        assert!(reports[0].rebuffer_count < reports[1].rebuffer_count);
+        
+        // Any ladder works; the top two renditions alone never go below 2.5 Mbps
+        let ladder: Vec<QualityLevel> = create_test_quality_levels().split_off(2);
+        let reports: Vec<SimulationReport> = compare_configs(&ladder, &trace, std::slice::from_ref(&aggressive)).unwrap();
+        assert!(reports[0].average_bitrate >= 2_500_000.0);
+        
+        // One invalid config fails the comparison before anything runs
+        let invalid: StreamerConfig = StreamerConfig { panic_factor: 1.5, ..StreamerConfig::default() };
+        assert_eq!(
+            compare_configs(&ladder, &trace, &[aggressive, invalid]).unwrap_err(),
+            ConfigError::OutOfRange { parameter: "panic_factor", value: 1.5 }
+        );
+        assert_eq!(compare_configs(&[], &trace, &[]).unwrap_err(), ConfigError::EmptyLadder);
+    }
+
+    #[test]
+    fn test_low_latency_mode() {
+        let clock = MockClock::new();
+        let mut normal = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        let mut low_latency = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        low_latency.set_latency_mode(LatencyMode::LowLatency { target_latency: Duration::from_secs(6) });
+        
+        for streamer in [&mut normal, &mut low_latency] {
+            streamer.set_clock(Box::new(clock.clone()));
+            for _ in 0..3 {
+                streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(2));
+            }
+            streamer.current_quality = 2;
+            streamer.buffer_state.current_level = Duration::from_secs(2);
+        }
+        
+        // 2s is a panic for a 30s target but most of a 6s latency budget,
+        // whose panic threshold scales down to 0.6s
+        assert_eq!(normal.buffer_zone(), BufferZone::Panic);
+        assert_eq!(low_latency.buffer_zone(), BufferZone::BelowTarget);
+        assert!(low_latency.calculate_buffer_factor() > normal.calculate_buffer_factor());
+        let normal_decision: QualityDecision = normal.get_next_quality_detailed();
+        let low_latency_decision: QualityDecision = low_latency.get_next_quality_detailed();
+        assert!(low_latency_decision.effective_bandwidth > normal_decision.effective_bandwidth);
+        
+        // A heavy upcoming segment would take 2.4s to fetch 2s of content at
+        // the measured 4 Mbps, which only low-latency mode refuses. Both sit
+        // at their own target level.
+        normal.buffer_state.current_level = Duration::from_secs(30);
+        low_latency.buffer_state.current_level = Duration::from_secs(6);
+        for streamer in [&mut normal, &mut low_latency] {
+            streamer.current_quality = 2;
+            streamer.set_next_segment_size_hint(Some(1_200_000));
+        }
+        assert_eq!(normal.get_next_quality(), 2);
+        assert_eq!(low_latency.get_next_quality(), 1);
+        
+        // Stepping back up is held at the live edge too: a 2s segment at 1 Mbps
+        // arrives in 1.2s, but at 2.5 Mbps it would take 3s
+        low_latency.set_next_segment_size_hint(Some(600_000));
+        let decision: QualityDecision = low_latency.get_next_quality_detailed();
+        assert_eq!(decision.quality, 1);
+        assert!(decision.target_quality > 1);
+        assert_eq!(decision.upswitch_suppressed_by, Some(SuppressionReason::LiveEdge));
+    }
+
+    #[test]
+    fn test_update_quality_levels_tracks_rendition() {
+        let mut levels: Vec<QualityLevel> = create_test_quality_levels();
+        levels.insert(2, QualityLevel { bitrate: 2_500_000, width: 1920, height: 1080, codec: "hevc".to_string() });
+        let mut streamer = AdaptiveBitrateStreamer::new(levels.clone());
+        streamer.current_quality = 2;
+        
+        // Swapped, the h264 twin at the same bitrate now comes first and the
+        // nearest-bitrate match alone would pick it
+        levels.swap(2, 3);
+        streamer.update_quality_levels(levels.clone()).unwrap();
+        assert_eq!(streamer.current_quality, 3);
+        assert_eq!(streamer.get_current_quality().codec, "hevc");
+        
+        // Once the rendition is withdrawn the nearest bitrate takes over
+        levels.remove(3);
+        streamer.update_quality_levels(levels).unwrap();
+        assert_eq!(streamer.get_current_quality().bitrate, 2_500_000);
+        assert_eq!(streamer.get_current_quality().codec, "h264");
+    }
+
+    #[test]
+    fn test_rebuffer_grace() {
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_rebuffer_grace(Duration::from_millis(100));
+        
+        // A 50ms blip, ended by the next segment landing
+        streamer.buffer_state.current_level = Duration::from_secs(1);
+        streamer.update_buffer_consumption(Duration::from_millis(1_050));
+        assert_eq!(streamer.rebuffer_count(), 0);
+        streamer.record_segment_download(500_000, Duration::from_secs(1), Duration::from_secs(4));
+        
+        // The same start, but the buffer stays empty past the grace period
+        streamer.update_buffer_consumption(Duration::from_millis(4_050));
+        assert_eq!(streamer.rebuffer_count(), 0);
+        streamer.update_buffer_consumption(Duration::from_millis(100));
+        assert_eq!(streamer.rebuffer_count(), 1);
+        streamer.update_buffer_consumption(Duration::from_millis(500));
+        assert_eq!(streamer.rebuffer_count(), 1);
+        assert_eq!(streamer.rebuffer_time(), Duration::from_millis(700));
+    }
+
+    #[test]
+    fn test_target_warmup() {
+        let clock = MockClock::new();
+        let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+        streamer.set_clock(Box::new(clock.clone()));
+        streamer.set_target_warmup(Duration::from_secs(20));
+        streamer.buffer_state.current_level = Duration::from_secs(10);
+        
+        // Ramps 5s -> 30s, so the same 10s buffer looks ever shallower
+        assert_eq!(streamer.effective_target_level(), Duration::from_secs(5));
+        assert_eq!(streamer.calculate_buffer_factor(), 1.0);
+        
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(streamer.effective_target_level(), Duration::from_millis(17_500));
+        let halfway_factor: f64 = streamer.calculate_buffer_factor();
+        assert!(halfway_factor < 1.0);
+        
+        clock.advance(Duration::from_secs(15));
+        assert_eq!(streamer.effective_target_level(), Duration::from_secs(30));
+        assert!(streamer.calculate_buffer_factor() < halfway_factor);
+        assert_eq!(streamer.effective_panic_threshold(), streamer.buffer_panic_threshold);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_bandwidth_utilization() {
+        let run = |bandwidths: &[u32]| -> (f64, f64) {
+            let clock = MockClock::new();
+            let mut streamer = AdaptiveBitrateStreamer::new(create_test_quality_levels());
+            streamer.set_clock(Box::new(clock.clone()));
+            assert_eq!(streamer.bandwidth_utilization(), 0.0);
+            let mut estimates: Vec<u32> = Vec::new();
+            for &bandwidth in bandwidths {
+                let segment_size: u32 = streamer.get_current_quality().bitrate / 2; // 4s segments
+                let download_duration: Duration = Duration::from_secs_f64(segment_size as f64 / bandwidth as f64);
+                clock.advance(download_duration);
+                streamer.record_download_with_concurrent_playback(
+                    segment_size,
+                    download_duration,
+                    Duration::from_secs(4),
+                    download_duration,
+                );
+                estimates.push(streamer.get_next_quality_detailed().estimated_bandwidth);
+            }
+            
+            // Played bitrate against the estimate the recent decisions saw
+            let recent: &[u32] = &estimates[estimates.len() - RECENT_SEGMENT_COUNT..];
+            let mean_estimate: f64 =
+                recent.iter().map(|&estimate| byte_rate_to_bitrate(estimate) as f64).sum::<f64>() / recent.len() as f64;
+            (streamer.bandwidth_utilization(), streamer.recent_played_bitrate() / mean_estimate)
+        };
+        
+        // Once the buffer fills, a steady 7 Mbps link carries the 5 Mbps top rendition
+        let (stable, expected_stable) = run(&[875_000; 40]);
+        // The same peak alternating with 2 Mbps dips keeps the player well
+        // under even the blended estimate
+        let (volatile, expected_volatile) = run(&[875_000, 250_000].repeat(20));
+        assert!((stable - expected_stable).abs() < 1e-9);
+        assert!((volatile - expected_volatile).abs() < 1e-9);
+        assert!(stable > 0.7);
+        assert!(volatile < stable);
+    }
+}